@@ -1,92 +1,70 @@
+mod commands;
 mod config;
-use std::error::Error;
-use std::rc::Rc;
+mod fmt;
+mod helper;
+mod runner;
+mod test;
+mod transcript;
 
-pub use config::{print_usage, Config};
+pub use config::{print_usage, Command, Config};
 
-use crate::pipeline::{evaluator, parse_to_locally_nameless, parse_tree, parser, runtime};
+use std::{fs, io, path::Path};
 
+use helper::ReplHelper;
+use runner::{Flow, Runner};
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
-struct Runner {
-    globals: runtime::Globals,
+pub fn run(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    match &config.command {
+        Command::Repl => run_repl(config),
+        Command::Fmt {
+            paths,
+            check,
+            width,
+        } => fmt::run(paths, *check, *width),
+        Command::Test { paths } => test::run(paths, config),
+        Command::Replay { paths, bless } => transcript::run(paths, *bless, config),
+    }
 }
 
-impl Runner {
-    fn new() -> Self {
-        let mut globals = runtime::Globals::new();
-        fn cast_to_integer(value: runtime::RTValue) -> Result<i64, String> {
-            match value {
-                runtime::RTValue::Integer(value) => Ok(value),
-                other => Err(format!("Expected number, got {}", other))?,
-            }
-        }
-        globals.define(
-            "plus",
-            runtime::RTValue::Function(Rc::new(|arg1, _| {
-                let arg1 = cast_to_integer(arg1)?;
-                Ok(runtime::RTValue::Function(Rc::new(move |arg2, _| {
-                    let arg2 = cast_to_integer(arg2)?;
-                    Ok(runtime::RTValue::Integer(arg1 + arg2))
-                })))
-            })),
-        );
-        globals.define(
-            "repeatedly",
-            runtime::RTValue::Function(Rc::new(|number_of_times, _| {
-                let number_of_times = cast_to_integer(number_of_times)?;
-                Ok(runtime::RTValue::Function(Rc::new(move |function, _| {
-                    let function = match function {
-                        runtime::RTValue::Function(f) => f,
-                        other => Err(format!("Expected function, got {}", other))?,
-                    };
-                    Ok(runtime::RTValue::Function(Rc::new(move |seed, globals| {
-                        let mut v = seed;
-                        for _ in 0..number_of_times {
-                            v = (*function)(v, globals)?;
-                        }
-                        Ok(v)
-                    })))
-                })))
-            })),
-        );
-        Self { globals }
+fn run_repl(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let history = config.history.as_ref();
+    let editor_config = rustyline::Config::builder()
+        .max_history_size(history.map_or(0, |history| history.max_size))
+        .build();
+    let mut rl = Editor::<ReplHelper>::with_config(editor_config)?;
+    if let Some(history) = history {
+        load_history(&mut rl, &history.path);
     }
-
-    fn run_stmt(&mut self, line: String) -> Result<(), Box<dyn Error>> {
-        let parsed_stmt = parser::parse_stmt(&line)?;
-        let (identifier, expr) = match parsed_stmt {
-            parse_tree::Stmt::Decl(parse_tree::Decl { identifier, expr }) => {
-                (Some(identifier), expr)
-            }
-            parse_tree::Stmt::Expr(expr) => (None, expr),
-        };
-        let runnable_expr = parse_to_locally_nameless::transform_expr(&expr);
-        let value = evaluator::eval(&runnable_expr, &self.globals)?;
-        if let Some(identifier) = identifier {
-            self.globals.define(&identifier[..], value);
-        } else {
-            println!("{}", value);
+    let mut runner = Runner::new(config);
+    if let Some(session) = &config.session {
+        if Path::new(session).exists() {
+            runner.restore(session, &mut io::stdout(), &mut io::stderr())?;
         }
-        Ok(())
     }
-}
-
-pub fn run(_config: &Config) -> Result<(), Box<dyn Error>> {
-    let mut rl = Editor::<()>::new()?;
-    if rl.load_history(".lambda_calculus_history").is_err() {}
-    let mut runner = Runner::new();
+    let mut helper = ReplHelper::default();
+    helper.update(runner.visible_globals());
+    rl.set_helper(Some(helper));
     loop {
+        // the helper keeps reading lines while the statement is incomplete
         let readline = rl.readline(">> ");
         match readline {
             Ok(line) => {
                 rl.add_history_entry(line.as_str());
-                if let Err(err) = runner.run_stmt(line) {
-                    eprintln!("Parse error: {}", err)
+                let flow = runner.run_line(&line, &mut io::stdout(), &mut io::stderr())?;
+                if flow == Flow::Quit {
+                    break;
+                }
+                if let Some(helper) = rl.helper_mut() {
+                    helper.update(runner.visible_globals());
                 }
             }
             Err(ReadlineError::Interrupted) => {
+                // abandons an unfinished statement, or else leaves
+                if rl.helper().is_some_and(ReplHelper::take_incomplete) {
+                    continue;
+                }
                 println!("^C");
                 break;
             }
@@ -97,6 +75,42 @@ pub fn run(_config: &Config) -> Result<(), Box<dyn Error>> {
             }
         }
     }
-    if rl.save_history(".lambda_calculus_history").is_err() {};
+    if let Some(history) = history {
+        save_history(&mut rl, &history.path);
+    }
+    if let Some(session) = &config.session {
+        if let Err(err) = runner.save(session) {
+            eprintln!("Warning: could not save session to {}: {}", session, err);
+        }
+    }
     Ok(())
 }
+
+/// A missing history file is fine, it will be created on exit. Other failures
+/// only deserve a warning, as the REPL works without history.
+fn load_history(rl: &mut Editor<ReplHelper>, path: &Path) {
+    match rl.load_history(path) {
+        Err(ReadlineError::Io(err)) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => eprintln!(
+            "Warning: could not read history from {}: {}",
+            path.display(),
+            err
+        ),
+        Ok(()) => {}
+    }
+}
+
+fn save_history(rl: &mut Editor<ReplHelper>, path: &Path) {
+    let result = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => fs::create_dir_all(dir).map_err(Into::into),
+        _ => Ok(()),
+    }
+    .and_then(|_| rl.save_history(path));
+    if let Err(err) = result {
+        eprintln!(
+            "Warning: could not save history to {}: {}",
+            path.display(),
+            err
+        );
+    }
+}
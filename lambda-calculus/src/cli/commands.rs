@@ -0,0 +1,150 @@
+use crate::parsers::{delim, one_char, string, Failure, Parser};
+use crate::pipeline::{errors::ParseError, parse_tree::Expr, parser};
+
+/// Lines starting with `:` control the REPL itself instead of being run as
+/// statements.
+#[derive(Debug, PartialEq)]
+pub enum MetaCommand {
+    Type(Expr),
+    Ast(Expr),
+    Env,
+    Load(String),
+    Save(String),
+    Restore(String),
+    Reload,
+    Reset,
+    Time(Expr),
+    Church,
+    Help,
+    Quit,
+}
+
+/// Names of every command, for completion.
+pub const NAMES: [&str; 12] = [
+    "type", "ast", "env", "load", "reload", "reset", "save", "restore", "time", "church", "help",
+    "quit",
+];
+
+/// Commands whose argument is a file path.
+pub const TAKING_PATHS: [&str; 3] = ["load", "save", "restore"];
+
+pub const HELP: &str = "\
+:type EXPR   show what kind of value EXPR evaluates to
+:ast EXPR    show the parse tree and locally nameless form of EXPR
+:env         list the defined globals
+:load FILE   run every statement in FILE
+:reload      load again every file loaded or imported so far
+:reset       forget every definition and loaded file
+:save FILE   write every import and definition to FILE, to restore them
+:restore FILE
+             run the definitions saved in FILE
+:time EXPR   evaluate EXPR and show how long it took
+:church      toggle showing Church numerals and booleans as what they encode
+:help        show this message
+:quit        leave the REPL";
+
+pub fn is_meta_command(line: &str) -> bool {
+    line.trim_start().starts_with(':')
+}
+
+pub fn parse_meta_command(line: &str) -> Result<MetaCommand, ParseError> {
+    Ok(meta_command().parse_str(line)?)
+}
+
+fn meta_command() -> impl Parser<Item = MetaCommand, ParseError = Failure> {
+    delim::whitespace()
+        .skip_many::<Failure>()
+        .then(string::expect(":"))
+        .then(
+            with_expr("type", MetaCommand::Type)
+                .falling_back(with_expr("ast", MetaCommand::Ast))
+                .falling_back(without_argument("env", || MetaCommand::Env))
+                .falling_back(with_path("load", MetaCommand::Load))
+                .falling_back(with_path("save", MetaCommand::Save))
+                .falling_back(without_argument("reload", || MetaCommand::Reload))
+                .falling_back(without_argument("reset", || MetaCommand::Reset))
+                .falling_back(with_path("restore", MetaCommand::Restore))
+                .falling_back(with_expr("time", MetaCommand::Time))
+                .falling_back(without_argument("church", || MetaCommand::Church))
+                .falling_back(without_argument("help", || MetaCommand::Help))
+                .falling_back(without_argument("quit", || MetaCommand::Quit)),
+        )
+        .also(delim::whitespace().skip_many())
+        .also(delim::EXPECT_END)
+}
+
+fn without_argument(
+    name: &'static str,
+    command: fn() -> MetaCommand,
+) -> impl Parser<Item = MetaCommand, ParseError = Failure> {
+    string::expect(name).map(move |_| command())
+}
+
+fn with_expr(
+    name: &'static str,
+    command: fn(Expr) -> MetaCommand,
+) -> impl Parser<Item = MetaCommand, ParseError = Failure> {
+    string::expect(name)
+        .then(delim::whitespace().skip_at_least_one())
+        .then(parser::expr())
+        .map(command)
+}
+
+fn with_path(
+    name: &'static str,
+    command: fn(String) -> MetaCommand,
+) -> impl Parser<Item = MetaCommand, ParseError = Failure> {
+    string::expect(name)
+        .then(delim::whitespace().skip_at_least_one())
+        .then(path())
+        .map(command)
+}
+
+fn path() -> impl Parser<Item = String, ParseError = Failure> {
+    one_char::matches(|c: char| !c.is_whitespace(), "file path")
+        .paired_with(string::many_chars_matching(|c: char| c != '\n'))
+        .map(|(first, rest)| format!("{}{}", first, rest).trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands_with_and_without_arguments() {
+        assert_eq!(parse_meta_command(":env").unwrap(), MetaCommand::Env);
+        assert_eq!(parse_meta_command("  :quit  ").unwrap(), MetaCommand::Quit);
+        assert_eq!(
+            parse_meta_command(":load lib/my file.lc ").unwrap(),
+            MetaCommand::Load("lib/my file.lc".to_string())
+        );
+        match parse_meta_command(r":type \x -> plus x +1").unwrap() {
+            MetaCommand::Type(expr) => {
+                assert_eq!(expr.to_string(), "(lambda x (apply (apply plus x) +1))")
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(
+            parse_meta_command(":restore today.lc").unwrap(),
+            MetaCommand::Restore("today.lc".to_string())
+        );
+        assert!(matches!(
+            parse_meta_command(":time f x").unwrap(),
+            MetaCommand::Time(_)
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_commands_and_missing_arguments() {
+        let error = parse_meta_command(":frobnicate").unwrap_err();
+        assert_eq!(error.found.as_deref(), Some("frob"));
+        assert!(error.expected.contains(&"`type`".to_string()));
+        assert!(error.expected.contains(&"`quit`".to_string()));
+
+        let error = parse_meta_command(":load").unwrap_err();
+        assert_eq!(error.expected, vec!["whitespace"]);
+        assert_eq!(error.found, None);
+
+        assert!(parse_meta_command(":env x").is_err());
+    }
+}
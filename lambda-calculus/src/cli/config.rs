@@ -1,11 +1,162 @@
-use std::{env, io};
+use std::{env, io, path::PathBuf};
 
-#[derive(Debug)]
-pub struct Config {}
+use crate::pipeline::{modules::Binding, printer};
+
+#[derive(Debug, Default)]
+pub struct Config {
+    pub command: Command,
+    pub allow_forward_references: bool,
+    /// Show functions that encode Church numerals or booleans as what they encode.
+    pub decode_church: bool,
+    /// Start without the definitions of [`crate::pipeline::prelude`].
+    pub skip_prelude: bool,
+    /// Where to keep the REPL history, if anywhere.
+    pub history: Option<History>,
+    /// File to restore definitions from on startup and save them to on exit.
+    pub session: Option<String>,
+    /// Define the natives of [`crate::pipeline::native::define_io`], which
+    /// let programs print and read. Without them, evaluation has no effects.
+    pub allow_io: bool,
+    /// Evaluate in continuation-passing style, which makes `callcc` available.
+    pub cps: bool,
+    /// Whether redefining a global changes the definitions that use it.
+    pub binding: Binding,
+}
+
+pub const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct History {
+    pub path: PathBuf,
+    pub max_size: usize,
+}
+
+#[derive(Debug, Default)]
+pub enum Command {
+    /// Read and run statements interactively.
+    #[default]
+    Repl,
+    /// Rewrite source files in their canonical layout, or only check that
+    /// they already are.
+    Fmt {
+        paths: Vec<String>,
+        check: bool,
+        width: usize,
+    },
+    /// Run the `test` statements in source files, and in the `.lc` files
+    /// found in directories.
+    Test { paths: Vec<String> },
+    /// Replay recorded REPL sessions, and check that the REPL still answers
+    /// as recorded, or record its new answers.
+    Replay { paths: Vec<String>, bless: bool },
+}
 
 impl Config {
-    pub fn from_strings(_arg_strs: impl Iterator<Item = String>) -> Result<Config, String> {
-        let result = Config {};
+    pub fn from_strings(
+        arg_strs: impl Iterator<Item = String>,
+        get_env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Config, String> {
+        let mut result = Config::default();
+        let mut history_path = None;
+        let mut history_size = DEFAULT_HISTORY_SIZE;
+        let mut no_history = false;
+        let mut arg_strs = arg_strs.peekable();
+        if arg_strs.peek().map(|arg| &arg[..]) == Some("fmt") {
+            arg_strs.next();
+            result.command = Command::Fmt {
+                paths: vec![],
+                check: false,
+                width: printer::DEFAULT_WIDTH,
+            };
+        } else if arg_strs.peek().map(|arg| &arg[..]) == Some("test") {
+            arg_strs.next();
+            result.command = Command::Test { paths: vec![] };
+        } else if arg_strs.peek().map(|arg| &arg[..]) == Some("replay") {
+            arg_strs.next();
+            result.command = Command::Replay {
+                paths: vec![],
+                bless: false,
+            };
+        }
+        while let Some(arg) = arg_strs.next() {
+            match (&arg[..], &mut result.command) {
+                ("--allow-forward-refs", _) => result.allow_forward_references = true,
+                ("--church", Command::Repl | Command::Replay { .. }) => result.decode_church = true,
+                ("--no-prelude", Command::Repl | Command::Test { .. } | Command::Replay { .. }) => {
+                    result.skip_prelude = true
+                }
+                ("--allow-io", Command::Repl | Command::Test { .. } | Command::Replay { .. }) => {
+                    result.allow_io = true
+                }
+                ("--cps", Command::Repl | Command::Test { .. } | Command::Replay { .. }) => {
+                    result.cps = true
+                }
+                ("--binding", Command::Repl | Command::Test { .. } | Command::Replay { .. }) => {
+                    result.binding = match arg_strs.next().as_deref() {
+                        Some("late") => Binding::Late,
+                        Some("snapshot") => Binding::Snapshot,
+                        _ => Err("--binding expects late or snapshot")?,
+                    };
+                }
+                ("--history", Command::Repl) => {
+                    history_path = Some(PathBuf::from(
+                        arg_strs.next().ok_or("--history expects a file")?,
+                    ));
+                }
+                ("--history-size", Command::Repl) => {
+                    history_size = arg_strs
+                        .next()
+                        .and_then(|value| value.parse().ok())
+                        .ok_or("--history-size expects a number of entries")?;
+                }
+                ("--no-history", Command::Repl) => no_history = true,
+                ("--session", Command::Repl) => {
+                    result.session = Some(arg_strs.next().ok_or("--session expects a file")?);
+                }
+                ("--check", Command::Fmt { check, .. }) => *check = true,
+                ("--bless", Command::Replay { bless, .. }) => *bless = true,
+                ("--width", Command::Fmt { width, .. }) => {
+                    *width = arg_strs
+                        .next()
+                        .and_then(|value| value.parse().ok())
+                        .ok_or("--width expects a number of columns")?;
+                }
+                (other, _) if other.starts_with('-') => Err(format!("Unknown argument {}", other))?,
+                (
+                    path,
+                    Command::Fmt { paths, .. }
+                    | Command::Test { paths }
+                    | Command::Replay { paths, .. },
+                ) => paths.push(path.to_string()),
+                (other, Command::Repl) => Err(format!("Unknown argument {}", other))?,
+            }
+        }
+        if let Command::Fmt { paths, .. } = &result.command {
+            if paths.is_empty() {
+                Err("fmt expects at least one file")?;
+            }
+        }
+        if let Command::Test { paths } = &result.command {
+            if paths.is_empty() {
+                Err("test expects at least one file or directory")?;
+            }
+        }
+        if let Command::Replay { paths, .. } = &result.command {
+            if paths.is_empty() {
+                Err("replay expects at least one transcript or directory")?;
+            }
+        }
+        if let Command::Repl = result.command {
+            if !no_history {
+                result.history =
+                    history_path
+                        .or_else(|| default_history_path(&get_env))
+                        .map(|path| History {
+                            path,
+                            max_size: history_size,
+                        });
+            }
+        }
         Ok(result)
     }
 
@@ -13,11 +164,139 @@ impl Config {
         Config::from_strings(
             // skip(1) to ignore argv[0]
             env::args().skip(1),
+            |var_name| env::var(var_name).ok(),
         )
     }
 }
 
+/// `$LAMBDA_CALCULUS_HISTORY`, or a file in the user's data directory as the
+/// XDG base directory spec places it.
+fn default_history_path(get_env: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    let non_empty = |var_name| get_env(var_name).filter(|value: &String| !value.is_empty());
+    if let Some(path) = non_empty("LAMBDA_CALCULUS_HISTORY") {
+        return Some(PathBuf::from(path));
+    }
+    let data_dir = non_empty("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| non_empty("HOME").map(|home| PathBuf::from(home).join(".local/share")))?;
+    Some(data_dir.join("lambda-calculus").join("history"))
+}
+
 pub fn print_usage(out: &mut impl io::Write) -> io::Result<()> {
-    out.write_fmt(format_args!("lambda-calculus\n"))?;
+    out.write_fmt(format_args!(
+        "lambda-calculus [--allow-forward-refs] [--church] [--no-prelude] [--allow-io]\n\
+         \x20               [--history FILE] [--history-size ENTRIES] [--no-history]\n\
+         \x20               [--session FILE] [--cps] [--binding late|snapshot]\n\
+         lambda-calculus fmt [--check] [--width COLUMNS] FILE...\n\
+         lambda-calculus test [--allow-forward-refs] [--no-prelude] [--allow-io] [--cps]\n\
+         \x20                    [--binding late|snapshot] PATH...\n\
+         lambda-calculus replay [--bless] [--allow-forward-refs] [--church] [--no-prelude]\n\
+         \x20                      [--allow-io] [--cps] [--binding late|snapshot] PATH...\n"
+    ))?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(args: &[&str], vars: &[(&str, &str)]) -> Result<Config, String> {
+        Config::from_strings(args.iter().map(|arg| arg.to_string()), |var_name| {
+            vars.iter()
+                .find(|(name, _)| *name == var_name)
+                .map(|(_, value)| value.to_string())
+        })
+    }
+
+    fn history_path(args: &[&str], vars: &[(&str, &str)]) -> Option<PathBuf> {
+        config(args, vars)
+            .unwrap()
+            .history
+            .map(|history| history.path)
+    }
+
+    #[test]
+    fn finds_the_history_file() {
+        let vars = [
+            ("HOME", "/home/me"),
+            ("XDG_DATA_HOME", "/data"),
+            ("LAMBDA_CALCULUS_HISTORY", "project.history"),
+        ];
+        assert_eq!(
+            history_path(&["--history", "here"], &vars),
+            Some(PathBuf::from("here"))
+        );
+        assert_eq!(
+            history_path(&[], &vars),
+            Some(PathBuf::from("project.history"))
+        );
+        assert_eq!(
+            history_path(&[], &vars[..2]),
+            Some(PathBuf::from("/data/lambda-calculus/history"))
+        );
+        assert_eq!(
+            history_path(&[], &[("HOME", "/home/me"), ("XDG_DATA_HOME", "")]),
+            Some(PathBuf::from(
+                "/home/me/.local/share/lambda-calculus/history"
+            ))
+        );
+        assert_eq!(history_path(&[], &[]), None);
+        assert_eq!(history_path(&["--no-history"], &vars), None);
+        assert_eq!(history_path(&["fmt", "file.lc"], &vars), None);
+    }
+
+    #[test]
+    fn reads_the_history_size() {
+        let history = config(&["--history-size", "10", "--history", "h"], &[])
+            .unwrap()
+            .history;
+        assert_eq!(
+            history,
+            Some(History {
+                path: PathBuf::from("h"),
+                max_size: 10
+            })
+        );
+        assert!(config(&["--history-size", "lots"], &[]).is_err());
+        assert!(config(&["fmt", "--no-history", "file.lc"], &[]).is_err());
+    }
+
+    #[test]
+    fn reads_the_test_command() {
+        let test = config(&["test", "--cps", "lib", "main.lc"], &[]).unwrap();
+        assert!(test.cps);
+        assert_eq!(test.history, None);
+        match test.command {
+            Command::Test { paths } => assert_eq!(paths, vec!["lib", "main.lc"]),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(config(&["test"], &[]).is_err());
+        assert!(config(&["test", "--church", "lib"], &[]).is_err());
+    }
+
+    #[test]
+    fn reads_the_replay_command() {
+        let replay = config(&["replay", "--church", "--bless", "sessions"], &[]).unwrap();
+        assert!(replay.decode_church);
+        assert_eq!(replay.history, None);
+        match replay.command {
+            Command::Replay { paths, bless } => {
+                assert_eq!(paths, vec!["sessions"]);
+                assert!(bless);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(config(&["replay"], &[]).is_err());
+        assert!(config(&["replay", "--check", "sessions"], &[]).is_err());
+        assert!(config(&["--bless"], &[]).is_err());
+    }
+
+    #[test]
+    fn reads_the_binding_mode() {
+        assert_eq!(config(&[], &[]).unwrap().binding, Binding::Late);
+        let snapshot = config(&["--binding", "snapshot"], &[]).unwrap();
+        assert_eq!(snapshot.binding, Binding::Snapshot);
+        assert!(config(&["--binding", "early"], &[]).is_err());
+        assert!(config(&["--binding"], &[]).is_err());
+    }
+}
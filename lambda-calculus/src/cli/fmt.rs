@@ -0,0 +1,33 @@
+use std::{error::Error, fs};
+
+use crate::pipeline::{diagnostics, parser, printer};
+
+pub fn run(paths: &[String], check: bool, width: usize) -> Result<(), Box<dyn Error>> {
+    let mut failures = 0;
+    for path in paths {
+        let source = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+        let stmts = match parser::parse_program(&source) {
+            Ok(stmts) => stmts,
+            Err(err) => {
+                eprintln!("{}", diagnostics::render(&err.into(), path, &source));
+                failures += 1;
+                continue;
+            }
+        };
+        let formatted = printer::print_program(&stmts, width);
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", path);
+            failures += 1;
+        } else {
+            fs::write(path, formatted).map_err(|err| format!("{}: {}", path, err))?;
+            println!("Formatted {}", path);
+        }
+    }
+    if failures > 0 {
+        Err(format!("{} of {} files failed", failures, paths.len()))?;
+    }
+    Ok(())
+}
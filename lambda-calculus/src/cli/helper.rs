@@ -0,0 +1,415 @@
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::BTreeMap;
+
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::{Hint, Hinter};
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Context, Helper};
+
+use super::{commands, runner};
+use crate::pipeline::{parser, runtime};
+
+const KEYWORD: &str = "\x1b[1;35m";
+const LITERAL: &str = "\x1b[33m";
+const LAMBDA: &str = "\x1b[36m";
+const MATCHING_PAREN: &str = "\x1b[1;34m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// Hints longer than this are cut short, to stay on one line.
+const MAX_HINT_LENGTH: usize = 48;
+
+/// Completes, hints, highlights and validates REPL input. It works on a
+/// snapshot of the globals, taken with [`ReplHelper::update`] after each line
+/// is run.
+#[derive(Default)]
+pub struct ReplHelper {
+    /// Each global name with a short description of its value.
+    globals: BTreeMap<String, String>,
+    filenames: FilenameCompleter,
+    /// Whether the input being read was found to go on in more lines.
+    incomplete: Cell<bool>,
+}
+
+impl ReplHelper {
+    pub fn update(&mut self, globals: Vec<(String, runtime::RTValue)>) {
+        self.globals = globals
+            .into_iter()
+            .map(|(name, value)| (name, summarize(&value.to_string())))
+            .collect();
+    }
+
+    /// Whether the input being read when it was interrupted was an unfinished
+    /// statement, rather than a fresh prompt. Forgets it for the next input.
+    pub fn take_incomplete(&self) -> bool {
+        self.incomplete.take()
+    }
+}
+
+impl Helper for ReplHelper {}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        if command_taking_path(&line[..pos]) {
+            return self.filenames.complete(line, pos, ctx);
+        }
+        let (start, candidates) = complete(line, pos, self.globals.keys().map(|name| &name[..]));
+        let candidates = candidates
+            .into_iter()
+            .map(|candidate| Pair {
+                display: candidate.clone(),
+                replacement: candidate,
+            })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+pub struct GlobalHint {
+    display: String,
+    completion: String,
+}
+
+impl Hint for GlobalHint {
+    fn display(&self) -> &str {
+        &self.display
+    }
+
+    fn completion(&self) -> Option<&str> {
+        Some(&self.completion)
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = GlobalHint;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<GlobalHint> {
+        hint(line, pos, &self.globals).map(|(completion, display)| GlobalHint {
+            display,
+            completion,
+        })
+    }
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        Cow::Owned(highlight(line, pos))
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("{}{}{}", DIM, hint, RESET))
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        // parentheses matching the cursor change as it moves
+        true
+    }
+}
+
+/// Keeps reading lines while the statement is incomplete, e.g. after a
+/// trailing `->`. An empty line submits it as it is, to see what is wrong with
+/// it.
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        let incomplete = needs_more_lines(ctx.input());
+        self.incomplete.set(incomplete);
+        Ok(if incomplete {
+            ValidationResult::Incomplete
+        } else {
+            ValidationResult::Valid(None)
+        })
+    }
+}
+
+fn needs_more_lines(input: &str) -> bool {
+    let ends_with_empty_line = input
+        .rsplit_once('\n')
+        .is_some_and(|(_, last)| last.trim().is_empty());
+    runner::is_incomplete(input) && !ends_with_empty_line
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_lowercase()
+}
+
+/// Start of the word that ends at `pos`.
+fn word_start(line: &str, pos: usize) -> usize {
+    line[..pos]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_identifier_char(*c))
+        .last()
+        .map_or(pos, |(i, _)| i)
+}
+
+fn command_taking_path(before_cursor: &str) -> bool {
+    let before_cursor = before_cursor.trim_start();
+    commands::TAKING_PATHS.iter().any(|command| {
+        before_cursor
+            .strip_prefix(':')
+            .and_then(|rest| rest.strip_prefix(command))
+            .is_some_and(|rest| rest.starts_with(char::is_whitespace))
+    })
+}
+
+/// Where the word being completed starts, and the names it may complete to:
+/// command names right after a leading `:`, and global names elsewhere.
+pub fn complete<'a>(
+    line: &str,
+    pos: usize,
+    globals: impl Iterator<Item = &'a str>,
+) -> (usize, Vec<String>) {
+    let start = word_start(line, pos);
+    let prefix = &line[start..pos];
+    let is_command = line[..start].trim_start() == ":";
+    let candidates: Vec<String> = if is_command {
+        commands::NAMES
+            .iter()
+            .filter(|name| name.starts_with(prefix))
+            .map(|name| name.to_string())
+            .collect()
+    } else {
+        globals
+            .filter(|name| name.starts_with(prefix))
+            .map(|name| name.to_string())
+            .collect()
+    };
+    (start, candidates)
+}
+
+/// When the cursor is at the end of the line, after the beginning of exactly
+/// one global name, what is missing from that name and a hint showing it
+/// completed together with its value.
+pub fn hint(
+    line: &str,
+    pos: usize,
+    globals: &BTreeMap<String, String>,
+) -> Option<(String, String)> {
+    if pos < line.len() || line.trim_start().starts_with(':') {
+        return None;
+    }
+    let start = word_start(line, pos);
+    let prefix = &line[start..pos];
+    if prefix.is_empty() {
+        return None;
+    }
+    let mut matches = globals
+        .range(prefix.to_string()..)
+        .take_while(|(name, _)| name.starts_with(prefix));
+    let (name, summary) = matches.next()?;
+    if matches.next().is_some() {
+        return None;
+    }
+    let missing = name[prefix.len()..].to_string();
+    let display = format!("{}  = {}", missing, summary);
+    Some((missing, display))
+}
+
+fn summarize(value: &str) -> String {
+    if value.chars().count() <= MAX_HINT_LENGTH {
+        value.to_string()
+    } else {
+        let cut: String = value.chars().take(MAX_HINT_LENGTH - 1).collect();
+        format!("{}…", cut)
+    }
+}
+
+/// Colours keywords, literals and lambda arrows, and the pair of parentheses
+/// next to the cursor, if any.
+pub fn highlight(line: &str, pos: usize) -> String {
+    let parens = matching_parens(line, pos);
+    let mut out = String::new();
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '(' | ')' if parens.is_some_and(|(open, close)| i == open || i == close) => {
+                out.push_str(MATCHING_PAREN);
+                out.push(c);
+                out.push_str(RESET);
+            }
+            '\\' => {
+                out.push_str(LAMBDA);
+                out.push(c);
+                out.push_str(RESET);
+            }
+            '-' if chars.peek().is_some_and(|(_, next)| *next == '>') => {
+                chars.next();
+                out.push_str(LAMBDA);
+                out.push_str("->");
+                out.push_str(RESET);
+            }
+            '+' | '-' if chars.peek().is_some_and(|(_, next)| next.is_ascii_digit()) => {
+                out.push_str(LITERAL);
+                out.push(c);
+                while let Some((_, digit)) = chars.next_if(|(_, next)| next.is_ascii_digit()) {
+                    out.push(digit);
+                }
+                out.push_str(RESET);
+            }
+            '"' => {
+                out.push_str(LITERAL);
+                out.push(c);
+                let mut escaped = false;
+                for (_, c) in chars.by_ref() {
+                    out.push(c);
+                    if c == '"' && !escaped {
+                        break;
+                    }
+                    escaped = c == '\\' && !escaped;
+                }
+                out.push_str(RESET);
+            }
+            c if is_identifier_char(c) => {
+                let mut word = c.to_string();
+                while let Some((_, c)) = chars.next_if(|(_, next)| is_identifier_char(*next)) {
+                    word.push(c);
+                }
+                if word == "let" || parser::KEYWORDS.contains(&&word[..]) {
+                    out.push_str(KEYWORD);
+                    out.push_str(&word);
+                    out.push_str(RESET);
+                } else {
+                    out.push_str(&word);
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Byte offsets of the parenthesis right before or at the cursor, and of its
+/// matching one.
+fn matching_parens(line: &str, pos: usize) -> Option<(usize, usize)> {
+    let bytes = line.as_bytes();
+    let at = [pos.checked_sub(1), Some(pos)]
+        .into_iter()
+        .flatten()
+        .find(|&i| matches!(bytes.get(i), Some(b'(') | Some(b')')))?;
+    let mut depth = 0;
+    if bytes[at] == b'(' {
+        for (i, byte) in bytes.iter().enumerate().skip(at) {
+            match byte {
+                b'(' => depth += 1,
+                b')' => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                return Some((at, i));
+            }
+        }
+    } else {
+        for i in (0..=at).rev() {
+            match bytes[i] {
+                b')' => depth += 1,
+                b'(' => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                return Some((i, at));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn globals() -> BTreeMap<String, String> {
+        [
+            ("plus", "<native plus/2>"),
+            ("pair", "\\a -> \\b -> \\c -> c a b"),
+            ("two", "2"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+    }
+
+    #[test]
+    fn completes_globals_and_commands() {
+        let globals = globals();
+        let names = || globals.keys().map(|name| &name[..]);
+        assert_eq!(
+            complete("f (p", 4, names()),
+            (3, vec!["pair".into(), "plus".into()])
+        );
+        assert_eq!(complete("let x = tw", 10, names()), (8, vec!["two".into()]));
+        assert_eq!(
+            complete(" :re", 4, names()),
+            (2, vec!["reload".into(), "reset".into(), "restore".into()])
+        );
+        assert_eq!(complete(":type pl", 8, names()), (6, vec!["plus".into()]));
+        assert!(command_taking_path(":load "));
+        assert!(!command_taking_path(":loader"));
+    }
+
+    #[test]
+    fn hints_the_only_global_with_its_value() {
+        let globals = globals();
+        assert_eq!(
+            hint("f pl", 4, &globals),
+            Some(("us".to_string(), "us  = <native plus/2>".to_string()))
+        );
+        assert_eq!(
+            hint("f two", 5, &globals),
+            Some(("".to_string(), "  = 2".to_string()))
+        );
+        assert_eq!(hint("f p", 3, &globals), None);
+        assert_eq!(hint("f pl x", 4, &globals), None);
+        assert_eq!(summarize(&"x".repeat(60)).chars().count(), MAX_HINT_LENGTH);
+    }
+
+    #[test]
+    fn reads_more_lines_until_the_statement_is_complete() {
+        assert!(needs_more_lines("let compose = \\f -> \\g ->"));
+        assert!(needs_more_lines(
+            "let compose = \\f -> \\g ->\n  \\x -> f (g"
+        ));
+        assert!(!needs_more_lines(
+            "let compose = \\f -> \\g ->\n  \\x -> f (g x)"
+        ));
+        assert!(!needs_more_lines("let compose = \\f -> \\g ->\n  "));
+        assert!(!needs_more_lines(":type"));
+    }
+
+    #[test]
+    fn highlights_tokens_and_matching_parentheses() {
+        let plain = |text: &str| {
+            text.replace(KEYWORD, "<k>")
+                .replace(LITERAL, "<l>")
+                .replace(LAMBDA, "<f>")
+                .replace(MATCHING_PAREN, "<p>")
+                .replace(RESET, "</>")
+        };
+        assert_eq!(
+            plain(&highlight("let k = \\x -> f (g -1) x", 0)),
+            "<k>let</> k = <f>\\</>x <f>-></> f (g <l>-1</>) x"
+        );
+        assert_eq!(
+            plain(&highlight("f (g (x)) y", 9)),
+            "f <p>(</>g (x)<p>)</> y"
+        );
+        assert_eq!(plain(&highlight("(f (x", 0)), "(f (x");
+        assert_eq!(plain(&highlight("letter", 0)), "letter");
+        assert_eq!(
+            plain(&highlight("try f catch g", 0)),
+            "<k>try</> f <k>catch</> g"
+        );
+        assert_eq!(
+            plain(&highlight(r#"f "a \" let" x"#, 0)),
+            r#"f <l>"a \" let"</> x"#
+        );
+    }
+}
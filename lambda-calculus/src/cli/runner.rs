@@ -0,0 +1,964 @@
+use std::{
+    fs,
+    io::{self, Write},
+    mem,
+    path::Path,
+    time::Instant,
+};
+
+use super::commands::{self, MetaCommand};
+use super::Config;
+use crate::interpreter::{Interpreter, Outcome};
+use crate::pipeline::errors::{Error, ParseError, Source};
+use crate::pipeline::modules::Binding;
+use crate::pipeline::native::{Console, Stdio};
+use crate::pipeline::{
+    diagnostics, parse_to_locally_nameless, parse_tree, parser, printer, runtime,
+};
+
+/// How many of the tests in a file passed and failed, see [`Runner::test_file`].
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct TestSummary {
+    pub passed: usize,
+    pub failed: usize,
+}
+
+/// What the REPL should do after a line has been run.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+/// Runs REPL input in an [`Interpreter`], writing results to one writer and
+/// errors to another, so that it can be driven without a terminal.
+pub struct Runner {
+    interpreter: Interpreter,
+    decode_church: bool,
+    // how `:reset` configures a fresh interpreter
+    skip_prelude: bool,
+    allow_io: bool,
+    cps: bool,
+    allow_forward_references: bool,
+    binding: Binding,
+    /// Where programs print and read, which stays the same across `:reset`.
+    console: runtime::Shared<dyn Console>,
+    loaded_files: Vec<String>,
+    /// Imports run so far, to run them again on `:reload` and `:save` them.
+    imports: Vec<parse_tree::Import>,
+    /// Definitions made so far, in the order they were made, to save them and
+    /// to tell which ones a redefinition affects. With snapshot bindings, this
+    /// keeps the ones that were redefined, as others may still use them.
+    definitions: Vec<Definition>,
+}
+
+struct Definition {
+    decl: parse_tree::Decl,
+    /// The global it defines.
+    key: String,
+    /// Globals the definition refers to.
+    dependencies: Vec<String>,
+}
+
+impl Runner {
+    pub fn new(config: &Config) -> Self {
+        Self::with_console(config, runtime::Shared::new(Stdio))
+    }
+
+    /// A runner whose programs print to and read from `console`, rather than
+    /// standard output and input.
+    pub fn with_console(config: &Config, console: runtime::Shared<dyn Console>) -> Self {
+        let mut runner = Self {
+            interpreter: Interpreter::without_prelude(),
+            decode_church: config.decode_church,
+            skip_prelude: config.skip_prelude,
+            allow_io: config.allow_io,
+            cps: config.cps,
+            allow_forward_references: config.allow_forward_references,
+            binding: config.binding,
+            console,
+            loaded_files: vec![],
+            imports: vec![],
+            definitions: vec![],
+        };
+        runner.interpreter = runner.new_interpreter();
+        runner
+    }
+
+    /// An interpreter configured as this runner was, with nothing defined yet.
+    fn new_interpreter(&self) -> Interpreter {
+        let interpreter = if self.skip_prelude {
+            Interpreter::without_prelude()
+        } else {
+            Interpreter::new()
+        };
+        interpreter
+            .allow_io(self.allow_io)
+            .cps(self.cps)
+            .allow_forward_references(self.allow_forward_references)
+            .binding(self.binding)
+            .console(self.console.clone())
+    }
+
+    /// The globals by the names they are used with, sorted. Previous versions
+    /// of redefined globals, and definitions of modules that can only be seen
+    /// through their exports, are left out.
+    pub fn visible_globals(&self) -> Vec<(String, runtime::RTValue)> {
+        self.interpreter.visible_globals()
+    }
+
+    pub fn run_line(
+        &mut self,
+        line: &str,
+        out: &mut impl Write,
+        err: &mut impl Write,
+    ) -> io::Result<Flow> {
+        if !commands::is_meta_command(line) {
+            if let Err(error) = self.run_stmt(line, out, err) {
+                report(err, &error, "<repl>", line)?;
+            }
+            return Ok(Flow::Continue);
+        }
+        let command = match commands::parse_meta_command(line) {
+            Ok(command) => command,
+            Err(error) => {
+                report(err, &error.into(), "<repl>", line)?;
+                return Ok(Flow::Continue);
+            }
+        };
+        match command {
+            MetaCommand::Type(expr) => match self
+                .interpreter
+                .evaluate_expr(&expr, &Source::new("<repl>", line))
+            {
+                Ok(value) => writeln!(out, "{}", describe(&value))?,
+                Err(error) => report(err, &error, "<repl>", line)?,
+            },
+            MetaCommand::Ast(expr) => {
+                writeln!(out, "{}", expr)?;
+                let source = Source::new("<repl>", line);
+                writeln!(
+                    out,
+                    "{}",
+                    parse_to_locally_nameless::transform_expr(&expr, &source)
+                )?;
+            }
+            MetaCommand::Time(expr) => {
+                let started = Instant::now();
+                match self
+                    .interpreter
+                    .evaluate_expr(&expr, &Source::new("<repl>", line))
+                {
+                    Ok(value) => {
+                        let elapsed = started.elapsed();
+                        writeln!(out, "{}", self.show(&value))?;
+                        writeln!(out, "Evaluated in {:?}", elapsed)?;
+                    }
+                    Err(error) => report(err, &error, "<repl>", line)?,
+                }
+            }
+            MetaCommand::Env => {
+                for (name, value) in self.visible_globals() {
+                    writeln!(out, "{} = {}", name, self.show(&value))?;
+                }
+            }
+            MetaCommand::Load(path) => {
+                // a file that failed would only fail again on `:reload`
+                if self.load(&path, out, err)? && !self.loaded_files.contains(&path) {
+                    self.loaded_files.push(path);
+                }
+            }
+            MetaCommand::Reload => {
+                // imported files may have changed too
+                self.interpreter.forget_modules();
+                for import in mem::take(&mut self.imports) {
+                    if let Err(error) = self.import(import) {
+                        report(err, &error, "<reload>", "")?;
+                    }
+                }
+                for path in self.loaded_files.clone() {
+                    self.load(&path, out, err)?;
+                }
+            }
+            MetaCommand::Reset => {
+                self.interpreter = self.new_interpreter();
+                self.loaded_files.clear();
+                self.imports.clear();
+                self.definitions.clear();
+            }
+            MetaCommand::Save(path) => match self.save(&path) {
+                Ok(count) => writeln!(out, "Saved {} definitions to {}", count, path)?,
+                Err(error) => writeln!(err, "Could not save {}: {}", path, error)?,
+            },
+            MetaCommand::Restore(path) => self.restore(&path, out, err)?,
+            MetaCommand::Church => {
+                self.decode_church = !self.decode_church;
+                writeln!(
+                    out,
+                    "Church decoding is {}",
+                    if self.decode_church { "on" } else { "off" }
+                )?;
+            }
+            MetaCommand::Help => writeln!(out, "{}", commands::HELP)?,
+            MetaCommand::Quit => return Ok(Flow::Quit),
+        }
+        Ok(Flow::Continue)
+    }
+
+    /// Runs the statements in the file at `path` as `:load` does, except that
+    /// it goes on after a test fails, and counts the tests that pass and fail.
+    /// Only what tests print goes to `out`. Anything else that fails stops the
+    /// file, and counts as a failure too.
+    pub fn test_file(
+        &mut self,
+        path: &str,
+        out: &mut impl Write,
+        err: &mut impl Write,
+    ) -> io::Result<TestSummary> {
+        let mut summary = TestSummary::default();
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) => {
+                summary.failed += 1;
+                writeln!(err, "Could not read {}: {}", path, error)?;
+                return Ok(summary);
+            }
+        };
+        let stmts = match parser::parse_program(&source) {
+            Ok(stmts) => stmts,
+            Err(error) => {
+                summary.failed += 1;
+                report(err, &error.into(), path, &source)?;
+                return Ok(summary);
+            }
+        };
+        let source = Source::new(path, source);
+        let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+        let directory = self.interpreter.enter_directory(directory.to_path_buf());
+        for stmt in stmts {
+            let name = match &stmt {
+                parse_tree::Stmt::Test(test) => Some(parse_tree::quote(&test.name)),
+                _ => None,
+            };
+            let result = match name {
+                Some(_) => self.run_parsed_stmt(stmt, &source, out, err),
+                None => self.run_parsed_stmt(stmt, &source, &mut io::sink(), err),
+            };
+            match (result, name) {
+                (Ok(()), Some(_)) => summary.passed += 1,
+                (Ok(()), None) => {}
+                (Err(error), Some(name)) => {
+                    summary.failed += 1;
+                    writeln!(out, "test {} ... FAILED", name)?;
+                    report(err, &error, path, &source.text)?;
+                }
+                (Err(error), None) => {
+                    summary.failed += 1;
+                    report(err, &error, path, &source.text)?;
+                    break;
+                }
+            }
+        }
+        self.interpreter.enter_directory(directory);
+        Ok(summary)
+    }
+
+    /// Runs every statement in the file at `path`, stopping at the first one
+    /// that fails. Tells whether they all ran.
+    fn load(&mut self, path: &str, out: &mut impl Write, err: &mut impl Write) -> io::Result<bool> {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) => {
+                writeln!(err, "Could not read {}: {}", path, error)?;
+                return Ok(false);
+            }
+        };
+        // imports in the file are relative to it
+        let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+        let directory = self.interpreter.enter_directory(directory.to_path_buf());
+        let source = Source::new(path, source);
+        let result = parser::parse_program(&source.text)
+            .map_err(Error::from)
+            .and_then(|stmts| {
+                stmts
+                    .into_iter()
+                    .try_for_each(|stmt| self.run_parsed_stmt(stmt, &source, out, err))
+            });
+        self.interpreter.enter_directory(directory);
+        match result {
+            Ok(()) => Ok(true),
+            Err(error) => {
+                report(err, &error, path, &source.text)?;
+                Ok(false)
+            }
+        }
+    }
+
+    fn show(&self, value: &runtime::RTValue) -> String {
+        if self.decode_church {
+            if let Some(decoded) = self.interpreter.decode_church(value) {
+                return decoded.to_string();
+            }
+        }
+        self.interpreter.show(value)
+    }
+
+    fn run_stmt(
+        &mut self,
+        line: &str,
+        out: &mut impl Write,
+        err: &mut impl Write,
+    ) -> Result<(), Error> {
+        let parsed_stmt = parser::parse_stmt(line)?;
+        self.run_parsed_stmt(parsed_stmt, &Source::new("<repl>", line), out, err)
+    }
+
+    /// Runs `stmt`, parsed from `source`.
+    fn run_parsed_stmt(
+        &mut self,
+        stmt: parse_tree::Stmt,
+        source: &runtime::Shared<Source>,
+        out: &mut impl Write,
+        err: &mut impl Write,
+    ) -> Result<(), Error> {
+        let outcome = self.interpreter.run_stmt(&stmt, source)?;
+        match (stmt, outcome) {
+            (
+                parse_tree::Stmt::Decl(decl),
+                Outcome::Defined {
+                    key,
+                    replaced,
+                    dependencies,
+                    ..
+                },
+            ) => {
+                let previous = self
+                    .definitions
+                    .iter()
+                    .rposition(|definition| definition.decl.identifier == decl.identifier);
+                // running the same source again, as `:reload` does, changes nothing
+                let unchanged = previous.is_some_and(|previous| {
+                    self.definitions[previous].decl.expr.to_string() == decl.expr.to_string()
+                });
+                if let (Some(previous), Binding::Late) = (previous, self.binding) {
+                    self.definitions.remove(previous);
+                }
+                let identifier = decl.identifier.clone();
+                self.definitions.push(Definition {
+                    decl,
+                    key: key.clone(),
+                    dependencies,
+                });
+                if let Some(replaced) = replaced.filter(|_| !unchanged) {
+                    // a warning that cannot be written is not an error either
+                    self.warn_about_dependents(&identifier, &replaced, &key, err)
+                        .ok();
+                }
+            }
+            (parse_tree::Stmt::Test(test), _) => {
+                writeln!(out, "test {} ... ok", parse_tree::quote(&test.name)).ok();
+            }
+            (parse_tree::Stmt::Import(import), _) => self.record_import(import),
+            (_, outcome) => {
+                // a closed stdout is not an error in the program being run
+                writeln!(out, "{}", self.show(&outcome.into_value())).ok();
+            }
+        }
+        Ok(())
+    }
+
+    /// Tells which definitions use the global `replaced`, which `identifier`
+    /// referred to until it was defined as `key`. With late binding they are
+    /// the same global, and the definitions change.
+    fn warn_about_dependents(
+        &self,
+        identifier: &str,
+        replaced: &str,
+        key: &str,
+        err: &mut impl Write,
+    ) -> io::Result<()> {
+        let mut affected = vec![replaced];
+        loop {
+            let before = affected.len();
+            for definition in &self.definitions {
+                if definition.key != key
+                    && !affected.contains(&&definition.key[..])
+                    && definition
+                        .dependencies
+                        .iter()
+                        .any(|dependency| affected.contains(&&dependency[..]))
+                {
+                    affected.push(&definition.key);
+                }
+            }
+            if affected.len() == before {
+                break;
+            }
+        }
+        let mut names: Vec<String> = vec![];
+        for definition in &self.definitions {
+            let name = format!("`{}`", definition.decl.identifier);
+            if affected[1..].contains(&&definition.key[..]) && !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        if names.is_empty() {
+            return Ok(());
+        }
+        if replaced == key {
+            writeln!(
+                err,
+                "Warning: redefining `{}` also changes {}, which {} it",
+                identifier,
+                names.join(", "),
+                if names.len() == 1 { "uses" } else { "use" }
+            )
+        } else {
+            writeln!(
+                err,
+                "Warning: the previous `{}` is still used by {}",
+                identifier,
+                names.join(", ")
+            )
+        }
+    }
+
+    /// Runs `import`, and remembers it for `:reload` and `:save`.
+    fn import(&mut self, import: parse_tree::Import) -> Result<(), Error> {
+        // imports hold no expressions for spans to point into
+        self.interpreter.run_stmt(
+            &parse_tree::Stmt::Import(import.clone()),
+            &Source::new("<reload>", ""),
+        )?;
+        self.record_import(import);
+        Ok(())
+    }
+
+    fn record_import(&mut self, import: parse_tree::Import) {
+        // saved relative to the current directory, like `:load` paths
+        let directory = self.interpreter.directory();
+        let import = if directory == Path::new("") {
+            import
+        } else {
+            let file = match &import.source {
+                parse_tree::ImportSource::Path(path) => path.clone(),
+                parse_tree::ImportSource::Name(name) => format!("{}.lc", name),
+            };
+            parse_tree::Import {
+                alias: import.qualifier().map(String::from),
+                source: parse_tree::ImportSource::Path(
+                    directory.join(file).to_string_lossy().into_owned(),
+                ),
+                span: import.span,
+            }
+        };
+        let already_imported = self
+            .imports
+            .iter()
+            .any(|imported| imported.source == import.source && imported.alias == import.alias);
+        if !already_imported {
+            self.imports.push(import);
+        }
+    }
+
+    /// Writes every definition made so far that is still in use to `path` as
+    /// source, returning how many there were.
+    pub fn save(&self, path: &str) -> io::Result<usize> {
+        let mut source = String::new();
+        for import in &self.imports {
+            source.push_str(&printer::print_stmt(
+                &parse_tree::Stmt::Import(import.clone()),
+                printer::DEFAULT_WIDTH,
+            ));
+            source.push('\n');
+        }
+        let definitions = self.definitions_in_dependency_order();
+        for definition in &definitions {
+            source.push_str(&printer::print_decl(
+                &definition.decl,
+                printer::DEFAULT_WIDTH,
+            ));
+            source.push('\n');
+        }
+        fs::write(path, source)?;
+        Ok(definitions.len())
+    }
+
+    /// Runs the definitions saved to `path` by [`Runner::save`].
+    pub fn restore(
+        &mut self,
+        path: &str,
+        out: &mut impl Write,
+        err: &mut impl Write,
+    ) -> io::Result<()> {
+        // definitions that refer to each other can only be saved in a cycle
+        let allow_forward_references = self.interpreter.set_allow_forward_references(true);
+        let result = self.load(path, out, err);
+        self.interpreter
+            .set_allow_forward_references(allow_forward_references);
+        result.map(|_| ())
+    }
+
+    /// The definitions in the order they were made, except that each one comes
+    /// after the ones it depends on, so that they can be run again in order.
+    /// Redefined ones are only kept if others still use them.
+    fn definitions_in_dependency_order(&self) -> Vec<&Definition> {
+        fn visit<'a>(
+            definitions: &'a [Definition],
+            index: usize,
+            visited: &mut Vec<bool>,
+            ordered: &mut Vec<&'a Definition>,
+        ) {
+            if visited[index] {
+                return;
+            }
+            visited[index] = true;
+            for dependency in &definitions[index].dependencies {
+                if let Some(dependency_index) = definitions
+                    .iter()
+                    .position(|definition| &definition.key == dependency)
+                {
+                    visit(definitions, dependency_index, visited, ordered);
+                }
+            }
+            ordered.push(&definitions[index]);
+        }
+        let mut visited = vec![false; self.definitions.len()];
+        let mut ordered = vec![];
+        for (index, definition) in self.definitions.iter().enumerate() {
+            let current = self.interpreter.resolve(&definition.decl.identifier);
+            if current.as_ref() == Some(&definition.key) {
+                visit(&self.definitions, index, &mut visited, &mut ordered);
+            }
+        }
+        ordered
+    }
+}
+
+/// Whether `input` is the beginning of a statement that goes on in the next
+/// lines, because the parser ran out of input before the statement was done,
+/// e.g. with an open parenthesis or after a trailing `->` or `=`.
+pub fn is_incomplete(input: &str) -> bool {
+    if input.trim().is_empty() || commands::is_meta_command(input) {
+        return false;
+    }
+    matches!(
+        parser::parse_stmt(input),
+        Err(ParseError { found: None, .. })
+    )
+}
+
+fn report(err: &mut impl Write, error: &Error, source_name: &str, source: &str) -> io::Result<()> {
+    writeln!(err, "{}", diagnostics::render(error, source_name, source))
+}
+
+/// What kind of value `value` is, as far as the runtime can tell.
+fn describe(value: &runtime::RTValue) -> String {
+    match value {
+        runtime::RTValue::Integer(_) => "number".to_string(),
+        runtime::RTValue::String(_) => "string".to_string(),
+        runtime::RTValue::Ref(_) => "ref".to_string(),
+        runtime::RTValue::Function(function) => match &function.origin {
+            runtime::FunctionOrigin::Lambda { .. } => "function".to_string(),
+            runtime::FunctionOrigin::Native {
+                name,
+                arity,
+                applied,
+            } => format!(
+                "native function {}/{}, expecting {} more argument(s)",
+                name,
+                arity,
+                arity - applied.len()
+            ),
+            runtime::FunctionOrigin::CallCC => "callcc".to_string(),
+            runtime::FunctionOrigin::Continuation(_) => "continuation".to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn run_lines(runner: &mut Runner, lines: &[&str]) -> (String, String) {
+        let (mut out, mut err) = (vec![], vec![]);
+        for line in lines {
+            runner.run_line(line, &mut out, &mut err).unwrap();
+        }
+        (
+            String::from_utf8(out).unwrap(),
+            String::from_utf8(err).unwrap(),
+        )
+    }
+
+    #[test]
+    fn runs_statements_and_meta_commands() {
+        let mut runner = Runner::new(&Config {
+            skip_prelude: true,
+            ..Config::default()
+        });
+        let (out, err) = run_lines(
+            &mut runner,
+            &[
+                "let id = \\x -> x",
+                "id +3",
+                ":type plus +1",
+                ":ast \\x -> id x",
+                ":env",
+            ],
+        );
+        assert_eq!(err, "");
+        // `par` is only defined when it can run in parallel
+        let par = if cfg!(feature = "sync") {
+            "par = <native par/3>\n"
+        } else {
+            ""
+        };
+        assert_eq!(
+            out,
+            format!(
+                "3
+native function plus/2, expecting 1 more argument(s)
+(lambda x (apply id x))
+(lambda (apply id #0))
+div = <native div/2>
+get = <native get/1>
+id = \\a -> a
+{}plus = <native plus/2>
+raise = <native raise/1>
+ref = <native ref/1>
+repeatedly = <native repeatedly/3>
+set = <native set/2>
+",
+                par
+            )
+        );
+        assert_eq!(
+            runner.run_line(":quit", &mut vec![], &mut vec![]).unwrap(),
+            Flow::Quit
+        );
+    }
+
+    #[test]
+    fn reports_errors_without_stopping() {
+        let mut runner = Runner::new(&Config::default());
+        let (out, err) = run_lines(&mut runner, &[":nope", ":type x", "+1"]);
+        assert_eq!(out, "1\n");
+        assert!(err.starts_with("Parse error: "));
+        assert!(err.contains("Resolve error: "));
+    }
+
+    #[test]
+    fn shows_runtime_errors_in_the_source_that_failed() {
+        let mut runner = Runner::new(&Config::default());
+        let (_, err) = run_lines(
+            &mut runner,
+            &["let f = \\x ->\n  x +1", "let g = +3", "f\n  g"],
+        );
+        assert!(err.contains(" --> <repl>:2:3\n  |\n2 |   x +1\n  |   ^^^^\n"));
+
+        let directory = TempDir::new("located");
+        let path = directory.write("lib.lc", "let h = \\x ->\n  x +1\n");
+        let path_str = path.to_str().unwrap();
+        let (_, err) = run_lines(&mut runner, &[&format!(":load {}", path_str), "h +3"]);
+        assert!(err.contains(&format!(
+            " --> {}:2:3\n  |\n2 |   x +1\n  |   ^^^^\n",
+            path_str
+        )));
+    }
+
+    #[test]
+    fn tells_incomplete_statements_from_errors() {
+        assert!(is_incomplete("let compose = \\f -> \\g ->"));
+        assert!(is_incomplete("let k ="));
+        assert!(is_incomplete("f (g\n  (h x)"));
+        assert!(!is_incomplete("f (g\n  (h x))"));
+        assert!(!is_incomplete("f (g x))"));
+        assert!(!is_incomplete(":type (f"));
+        assert!(!is_incomplete(""));
+
+        let mut runner = Runner::new(&Config::default());
+        let (out, err) = run_lines(&mut runner, &["let k = \\x ->\n  \\y -> x", "k +1 +2"]);
+        assert_eq!((out.as_str(), err.as_str()), ("1\n", ""));
+    }
+
+    #[test]
+    fn saves_definitions_after_their_dependencies() {
+        let config = Config {
+            allow_forward_references: true,
+            ..Config::default()
+        };
+        let mut runner = Runner::new(&config);
+        let (_, err) = run_lines(
+            &mut runner,
+            &[
+                "let twice = \\f -> \\x -> f (f x)",
+                "let addfour = \\x -> twice twice inc x",
+                "let inc = plus +1",
+                "let twice = \\f -> \\x -> f (f x)",
+                "let loop = \\x -> loop x",
+            ],
+        );
+        assert_eq!(err, "");
+        let directory = TempDir::new("session");
+        let path = directory.path().join("session.lc");
+        let path_str = path.to_str().unwrap();
+        assert_eq!(runner.save(path_str).unwrap(), 4);
+        let saved = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            saved,
+            "let twice = \\f -> \\x -> f (f x)
+let inc = plus +1
+let addfour = \\x -> twice twice inc x
+let loop = \\x -> loop x
+"
+        );
+
+        let mut restored = Runner::new(&Config::default());
+        let (out, err) = run_lines(
+            &mut restored,
+            &[&format!(":restore {}", path_str), "addfour +0"],
+        );
+        assert_eq!((out.as_str(), err.as_str()), ("4\n", ""));
+    }
+
+    #[test]
+    fn warns_which_definitions_a_redefinition_changes() {
+        let mut runner = Runner::new(&Config::default());
+        let (out, err) = run_lines(
+            &mut runner,
+            &[
+                "let offset = +1",
+                "let shift = \\x -> plus x offset",
+                "let twice = \\x -> shift (shift x)",
+                "let offset = +10",
+                "twice +0",
+                "let offset = +10",
+            ],
+        );
+        assert_eq!(out, "20\n");
+        assert_eq!(
+            err,
+            "Warning: redefining `offset` also changes `shift`, `twice`, which use it\n"
+        );
+    }
+
+    #[test]
+    fn keeps_previous_values_with_snapshot_binding() {
+        let mut runner = Runner::new(&Config {
+            binding: Binding::Snapshot,
+            ..Config::default()
+        });
+        let (out, err) = run_lines(
+            &mut runner,
+            &[
+                "let offset = +1",
+                "let shift = \\x -> plus x offset",
+                "let offset = plus offset +9",
+                "let plus = \\a -> \\b -> a",
+                "shift offset",
+                "plus +1 +2",
+                ":env",
+            ],
+        );
+        assert_eq!(
+            err,
+            "Warning: the previous `offset` is still used by `shift`\n\
+             Warning: the previous `plus` is still used by `shift`, `offset`\n"
+        );
+        assert!(out.starts_with("11\n1\n"));
+        assert!(out.contains("offset = 10\n"));
+        // shift still uses the first `plus` and `offset`
+        assert!(out.contains("shift = \\a -> plus@1 a offset@1\n"));
+
+        let directory = TempDir::new("snapshot");
+        let path = directory.path().join("session.lc");
+        let path_str = path.to_str().unwrap();
+        assert_eq!(runner.save(path_str).unwrap(), 4);
+        let mut restored = Runner::new(&Config {
+            binding: Binding::Snapshot,
+            ..Config::default()
+        });
+        let (out, _) = run_lines(
+            &mut restored,
+            &[&format!(":restore {}", path_str), "shift offset"],
+        );
+        assert_eq!(out, "11\n");
+    }
+
+    #[test]
+    fn starts_with_the_prelude_unless_asked_not_to() {
+        let mut runner = Runner::new(&Config::default());
+        let (out, err) = run_lines(&mut runner, &["let id = +1", ":reset", "two (plus +1) +0"]);
+        assert_eq!((out.as_str(), err.as_str()), ("2\n", ""));
+        let mut runner = Runner::new(&Config {
+            skip_prelude: true,
+            ..Config::default()
+        });
+        let (_, err) = run_lines(&mut runner, &["two"]);
+        assert!(err.starts_with("Resolve error: Unbound global `two`"));
+    }
+
+    #[test]
+    fn does_io_only_when_allowed() {
+        let directory = TempDir::new("io");
+        let path = directory.write("text.txt", "some \"text\"\n");
+        let readfile = format!("readfile \"{}\"", path.to_str().unwrap());
+
+        let mut runner = Runner::new(&Config::default());
+        let (_, err) = run_lines(&mut runner, &[&readfile]);
+        assert!(err.starts_with("Resolve error: Unbound global `readfile`"));
+
+        let mut runner = Runner::new(&Config {
+            allow_io: true,
+            ..Config::default()
+        });
+        let (out, err) = run_lines(&mut runner, &[&readfile, ":type println \"\""]);
+        assert_eq!(err, "");
+        assert_eq!(out, "\"some \\\"text\\\"\\n\"\nstring\n");
+    }
+
+    #[test]
+    fn offers_callcc_in_cps_mode() {
+        let mut runner = Runner::new(&Config::default());
+        let (_, err) = run_lines(&mut runner, &[r"callcc \k -> k +1"]);
+        assert!(err.starts_with("Resolve error: Unbound global `callcc`"));
+
+        let mut runner = Runner::new(&Config {
+            cps: true,
+            ..Config::default()
+        });
+        let (out, err) = run_lines(
+            &mut runner,
+            &[
+                r"let early = \x -> callcc \return -> plus +1 (return x)",
+                "early +5",
+                ":type callcc",
+                "callcc \\k -> k",
+            ],
+        );
+        assert_eq!(err, "");
+        assert_eq!(out, "5\ncallcc\n<continuation>\n");
+    }
+
+    #[test]
+    fn loads_reloads_and_resets() {
+        let directory = TempDir::new("runner");
+        let path = directory.write("count.lc", "let count = +2\ncount\n");
+        let path_str = path.to_str().unwrap();
+        let mut runner = Runner::new(&Config::default());
+        let load = format!(":load {}", path_str);
+        let (out, err) = run_lines(&mut runner, &[&load, "count"]);
+        assert_eq!((out.as_str(), err.as_str()), ("2\n2\n", ""));
+
+        fs::write(&path, "let count = plus +1 +1\n").unwrap();
+        let (out, err) = run_lines(&mut runner, &[":reload", "count", ":reset", "count"]);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(out, "2\n");
+        assert!(err.contains("Resolve error: "));
+
+        let missing = format!(":load {}", path_str);
+        let (_, err) = run_lines(&mut runner, &[&missing, ":reload"]);
+        assert_eq!(err.matches("Could not read").count(), 1);
+    }
+
+    #[test]
+    fn imports_modules_and_reloads_them() {
+        let directory = TempDir::new("imports");
+        let library = directory.write(
+            "shapes.lc",
+            "export area\nlet area = \\x -> side x\nlet side = plus +1\n",
+        );
+        let main = directory.write("main.lc", "import shapes as s\nlet side = s.area +2\n");
+        let mut runner = Runner::new(&Config {
+            allow_forward_references: true,
+            ..Config::default()
+        });
+        let load = format!(":load {}", main.display());
+        let (out, err) = run_lines(
+            &mut runner,
+            &[&load, "side", "s.area +3", "s.side", "export side"],
+        );
+        assert_eq!(out, "3\n4\n");
+        assert!(err.contains("Module `s` does not export `side`"));
+        assert!(err.contains("Only modules can export definitions"));
+        let (out, err) = run_lines(&mut runner, &[":env"]);
+        assert_eq!(err, "");
+        assert!(out.contains("\ns.area = "));
+        assert!(out.contains("\nside = "));
+        assert!(!out.contains("\nshapes."));
+
+        fs::write(&library, "let area = \\x -> plus x x\n").unwrap();
+        let (out, err) = run_lines(&mut runner, &[":reload", "side", "s.area +3"]);
+        assert_eq!((out.as_str(), err.as_str()), ("4\n6\n", ""));
+
+        let session = directory.path().join("session.lc");
+        runner.save(session.to_str().unwrap()).unwrap();
+        let saved = fs::read_to_string(&session).unwrap();
+        assert_eq!(
+            saved,
+            format!(
+                "import {:?} as s\nlet side = s.area +2\n",
+                library.to_str().unwrap()
+            )
+        );
+    }
+    #[test]
+    fn runs_the_tests_in_a_file() {
+        let directory = TempDir::new("tests");
+        let path = directory.write(
+            "tests.lc",
+            concat!(
+                "let double = \\x -> plus x x\n",
+                "test \"doubles\" = double +2 == +4\n",
+                "double +1\n",
+                "test \"adds one\" = double +2 == +5\n",
+                "test \"numerals\" = add two two == mul two two\n",
+            ),
+        );
+        let mut runner = Runner::new(&Config::default());
+        let (mut out, mut err) = (vec![], vec![]);
+        let summary = runner
+            .test_file(path.to_str().unwrap(), &mut out, &mut err)
+            .unwrap();
+        assert_eq!(
+            summary,
+            TestSummary {
+                passed: 2,
+                failed: 1
+            }
+        );
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                "test \"doubles\" ... ok\n",
+                "test \"adds one\" ... FAILED\n",
+                "test \"numerals\" ... ok\n",
+            )
+        );
+        let err = String::from_utf8(err).unwrap();
+        assert!(err.contains("`adds one` evaluated to 4, but expected 5"));
+
+        // a failing definition stops the file, a failing test does not
+        fs::write(
+            &path,
+            concat!(
+                "test \"undefined\" = nope == +1\n",
+                "let broken = nope\n",
+                "test \"skipped\" = +1 == +1\n",
+            ),
+        )
+        .unwrap();
+        let summary = runner
+            .test_file(path.to_str().unwrap(), &mut vec![], &mut vec![])
+            .unwrap();
+        assert_eq!(
+            summary,
+            TestSummary {
+                passed: 0,
+                failed: 2
+            }
+        );
+    }
+}
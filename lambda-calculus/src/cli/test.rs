@@ -0,0 +1,75 @@
+use std::{error::Error, fs, io, path::Path};
+
+use super::runner::Runner;
+use super::Config;
+
+/// Runs the tests in every file given, and in every `.lc` file found in the
+/// directories given, each file starting from a fresh set of globals.
+pub fn run(paths: &[String], config: &Config) -> Result<(), Box<dyn Error>> {
+    let mut files = vec![];
+    for path in paths {
+        find_sources(Path::new(path), "lc", &mut files)
+            .map_err(|err| format!("{}: {}", path, err))?;
+    }
+    let (mut passed, mut failed) = (0, 0);
+    for file in &files {
+        println!("{}", file);
+        let mut runner = Runner::new(config);
+        let summary = runner.test_file(file, &mut io::stdout(), &mut io::stderr())?;
+        passed += summary.passed;
+        failed += summary.failed;
+    }
+    println!("{} passed, {} failed", passed, failed);
+    if failed > 0 {
+        Err(format!("{} of {} tests failed", failed, passed + failed))?;
+    }
+    Ok(())
+}
+
+/// Adds `path` to `files` if it is a file, or else the files under it with the
+/// given extension, in order.
+pub(super) fn find_sources(
+    path: &Path,
+    extension: &str,
+    files: &mut Vec<String>,
+) -> io::Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_string_lossy().into_owned());
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() || entry.extension().is_some_and(|found| found == extension) {
+            find_sources(&entry, extension, files)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn finds_sources_in_directories() {
+        let directory = TempDir::new("test");
+        for file in ["b.lc", "a.lc", "notes.txt", "lib/c.lc"] {
+            directory.write(file, "");
+        }
+        let mut files = vec![];
+        find_sources(directory.path(), "lc", &mut files).unwrap();
+        find_sources(&directory.path().join("notes.txt"), "lc", &mut files).unwrap();
+        let names: Vec<&str> = files
+            .iter()
+            .map(|file| {
+                file.strip_prefix(directory.path().to_str().unwrap())
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(names, vec!["/a.lc", "/b.lc", "/lib/c.lc", "/notes.txt"]);
+    }
+}
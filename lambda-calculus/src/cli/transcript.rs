@@ -0,0 +1,310 @@
+use std::{
+    error::Error,
+    fmt, fs,
+    io::{self, Write},
+    mem,
+    path::Path,
+    sync::Mutex,
+};
+
+use super::runner::{Flow, Runner};
+use super::test::find_sources;
+use super::Config;
+use crate::pipeline::{native::Console, runtime::Shared};
+
+/// Replays every transcript given, and every `.transcript` file found in the
+/// directories given, each in a fresh REPL, and shows where the REPL now
+/// answers differently. With `bless`, the transcripts are rewritten with the
+/// new answers instead.
+pub fn run(paths: &[String], bless: bool, config: &Config) -> Result<(), Box<dyn Error>> {
+    let mut files = vec![];
+    for path in paths {
+        find_sources(Path::new(path), "transcript", &mut files)
+            .map_err(|err| format!("{}: {}", path, err))?;
+    }
+    let mut failures = 0;
+    for file in &files {
+        let text = fs::read_to_string(file).map_err(|err| format!("{}: {}", file, err))?;
+        let expected = Transcript::parse(&text);
+        let actual = replay(&expected, config)?;
+        if actual == expected {
+            println!("{} ... ok", file);
+        } else if bless {
+            fs::write(file, actual.to_string()).map_err(|err| format!("{}: {}", file, err))?;
+            println!("Blessed {}", file);
+        } else {
+            println!("{} ... FAILED", file);
+            print!("{}", differences(&expected, &actual));
+            failures += 1;
+        }
+    }
+    if failures > 0 {
+        Err(format!(
+            "{} of {} transcripts failed, run with --bless to accept the new output",
+            failures,
+            files.len()
+        ))?;
+    }
+    Ok(())
+}
+
+/// A recorded REPL session: what was typed at each prompt, and what the REPL
+/// answered to it, e.g.
+///
+/// ```text
+/// >> let double = \x -> plus x x
+/// >> double +2
+/// 4
+/// ```
+///
+/// Lines starting with `..` right after an input continue it, as at the REPL.
+/// Anything before the first prompt is kept as it is.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Transcript {
+    preamble: String,
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Entry {
+    input: String,
+    /// Results and errors alike, in the order they were written, as lines
+    /// that each end in `\n`.
+    output: String,
+}
+
+impl Transcript {
+    fn parse(text: &str) -> Self {
+        let mut transcript = Transcript::default();
+        for line in text.lines() {
+            if let Some(input) = prompted(line, ">>") {
+                transcript.entries.push(Entry {
+                    input: input.to_string(),
+                    output: String::new(),
+                });
+                continue;
+            }
+            let Some(entry) = transcript.entries.last_mut() else {
+                transcript.preamble.push_str(line);
+                transcript.preamble.push('\n');
+                continue;
+            };
+            match prompted(line, "..") {
+                Some(more) if entry.output.is_empty() => {
+                    entry.input.push('\n');
+                    entry.input.push_str(more);
+                }
+                _ => {
+                    entry.output.push_str(line);
+                    entry.output.push('\n');
+                }
+            }
+        }
+        transcript
+    }
+}
+
+fn prompted<'a>(line: &'a str, prompt: &str) -> Option<&'a str> {
+    let rest = line.strip_prefix(prompt)?;
+    Some(rest.strip_prefix(' ').unwrap_or(rest))
+}
+
+impl fmt::Display for Transcript {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.preamble)?;
+        for entry in &self.entries {
+            for (i, line) in entry.input.split('\n').enumerate() {
+                writeln!(f, "{} {}", if i == 0 { ">>" } else { ".." }, line)?;
+            }
+            f.write_str(&entry.output)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the inputs of `transcript` in a fresh REPL, recording what it answers
+/// to each, including what programs print. Inputs after a `:quit` are left
+/// out.
+fn replay(transcript: &Transcript, config: &Config) -> io::Result<Transcript> {
+    let capture = Capture::default();
+    let mut runner = Runner::with_console(config, Shared::new(capture.clone()));
+    let mut entries = vec![];
+    for entry in &transcript.entries {
+        let flow = runner.run_line(&entry.input, &mut capture.clone(), &mut capture.clone())?;
+        let written = mem::take(&mut *capture.0.lock().unwrap());
+        let mut output = String::from_utf8_lossy(&written).into_owned();
+        // so that the next prompt starts a line of its own
+        if !output.is_empty() && !output.ends_with('\n') {
+            output.push('\n');
+        }
+        entries.push(Entry {
+            input: entry.input.clone(),
+            output,
+        });
+        if flow == Flow::Quit {
+            break;
+        }
+    }
+    Ok(Transcript {
+        preamble: transcript.preamble.clone(),
+        entries,
+    })
+}
+
+/// Lets results, errors and what programs print go to the same buffer, as
+/// they would to a terminal.
+#[derive(Clone, Default)]
+struct Capture(Shared<Mutex<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Console for Capture {
+    fn write(&self, text: &str) -> io::Result<()> {
+        self.0.lock().unwrap().extend_from_slice(text.as_bytes());
+        Ok(())
+    }
+
+    /// A transcript records no input for programs to read.
+    fn read_line(&self) -> io::Result<Option<String>> {
+        Ok(None)
+    }
+}
+
+/// Each input that was answered differently, followed by the lines of the
+/// answer that went missing, marked with `-`, and the new ones, marked with
+/// `+`.
+fn differences(expected: &Transcript, actual: &Transcript) -> String {
+    let mut text = String::new();
+    let entries = expected.entries.len().max(actual.entries.len());
+    for i in 0..entries {
+        let (expected, actual) = (expected.entries.get(i), actual.entries.get(i));
+        if output(expected) == output(actual) {
+            continue;
+        }
+        let input = expected.or(actual).map_or("", |entry| &entry.input);
+        for (i, line) in input.split('\n').enumerate() {
+            text.push_str(&format!("{} {}\n", if i == 0 { ">>" } else { ".." }, line));
+        }
+        for (mark, line) in diff_lines(output(expected), output(actual)) {
+            text.push_str(&format!("{} {}\n", mark, line));
+        }
+    }
+    text
+}
+
+fn output(entry: Option<&Entry>) -> &str {
+    entry.map_or("", |entry| &entry.output)
+}
+
+/// The lines of `expected` and `actual`, marked with `-` if only in the first,
+/// `+` if only in the second, and ` ` if in both, keeping as many in both as
+/// possible.
+fn diff_lines<'a>(expected: &'a str, actual: &'a str) -> Vec<(char, &'a str)> {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    // common[i][j] is how many lines expected[i..] and actual[j..] can share
+    let mut common = vec![vec![0; actual.len() + 1]; expected.len() + 1];
+    for (i, expected_line) in expected.iter().enumerate().rev() {
+        for (j, actual_line) in actual.iter().enumerate().rev() {
+            common[i][j] = if expected_line == actual_line {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j, mut lines) = (0, 0, vec![]);
+    while i < expected.len() || j < actual.len() {
+        let (more_expected, more_actual) = (i < expected.len(), j < actual.len());
+        if more_expected && more_actual && expected[i] == actual[j] {
+            lines.push((' ', expected[i]));
+            (i, j) = (i + 1, j + 1);
+        } else if !more_actual || (more_expected && common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(('-', expected[i]));
+            i += 1;
+        } else {
+            lines.push(('+', actual[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SESSION: &str = "\
+A session with the prelude.
+>> let double = \\x -> plus x x
+>> let compose = \\f -> \\g ->
+..   \\x -> f (g x)
+>> compose double double +1
+4
+>> nope
+Unbound variable: nope
+";
+
+    #[test]
+    fn reads_and_writes_transcripts() {
+        let transcript = Transcript::parse(SESSION);
+        assert_eq!(transcript.preamble, "A session with the prelude.\n");
+        let inputs: Vec<&str> = transcript
+            .entries
+            .iter()
+            .map(|entry| &entry.input[..])
+            .collect();
+        assert_eq!(
+            inputs,
+            vec![
+                r"let double = \x -> plus x x",
+                "let compose = \\f -> \\g ->\n  \\x -> f (g x)",
+                "compose double double +1",
+                "nope"
+            ]
+        );
+        assert_eq!(transcript.entries[2].output, "4\n");
+        assert_eq!(transcript.to_string(), SESSION);
+    }
+
+    #[test]
+    fn replays_sessions_and_shows_what_changed() {
+        let expected = Transcript::parse(SESSION);
+        let actual = replay(&expected, &Config::default()).unwrap();
+        assert_eq!(actual.entries[2].output, "4\n");
+        assert!(actual.entries[3].output.contains("Unbound global `nope`"));
+        assert_eq!(
+            differences(&expected, &actual)
+                .lines()
+                .take(3)
+                .collect::<Vec<_>>(),
+            vec![
+                ">> nope",
+                "- Unbound variable: nope",
+                "+ Resolve error: Unbound global `nope`"
+            ]
+        );
+
+        let quitting = Transcript::parse(">> +1\n1\n>> :quit\n>> +2\n2\n");
+        let actual = replay(&quitting, &Config::default()).unwrap();
+        assert_eq!(actual.to_string(), ">> +1\n1\n>> :quit\n");
+        assert_eq!(differences(&quitting, &actual), ">> +2\n- 2\n");
+    }
+
+    #[test]
+    fn diffs_lines() {
+        assert_eq!(
+            diff_lines("a\nb\nc\n", "a\nc\nd\n"),
+            vec![(' ', "a"), ('-', "b"), (' ', "c"), ('+', "d")]
+        );
+        assert_eq!(diff_lines("", "a\n"), vec![('+', "a")]);
+    }
+}
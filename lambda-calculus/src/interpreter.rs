@@ -0,0 +1,468 @@
+use std::{
+    fmt::Display,
+    fs, io, mem,
+    path::{Path, PathBuf},
+};
+
+use crate::pipeline::{
+    church::{self, Decoded},
+    cps,
+    errors::{Error, ModuleError, ModuleErrorKind, Source},
+    evaluator::{self, BuildOptions},
+    locally_nameless_tree::Expr,
+    modules::{self, Binding, Modules, Namespace},
+    native::{self, Console, FromRTValue, Native1, Native2, Native3},
+    parse_tree, parser, prelude, readback,
+    runtime::{Globals, RTValue, Shared, ThreadSafe},
+    testing,
+};
+
+/// Runs lambda-calculus programs from Rust.
+///
+/// ```
+/// use lambda_calculus::Interpreter;
+///
+/// let mut interpreter = Interpreter::new();
+/// interpreter.eval_str(r"let double = \x -> plus x x").unwrap();
+/// assert_eq!(interpreter.eval_as::<i64>("double +21").unwrap(), 42);
+/// ```
+pub struct Interpreter {
+    globals: Globals,
+    allow_forward_references: bool,
+    /// Evaluate with [`cps::eval`] rather than [`evaluator::eval`].
+    cps: bool,
+    namespace: Namespace,
+    modules: Modules,
+    /// Where imports are found: the directory of the file being loaded, or the
+    /// current one.
+    directory: PathBuf,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    /// An interpreter with the built-in natives and the prelude defined.
+    pub fn new() -> Self {
+        let mut interpreter = Self::without_prelude();
+        prelude::load(&mut interpreter.globals).expect("the prelude is valid");
+        interpreter
+    }
+
+    /// An interpreter with only the built-in natives defined.
+    pub fn without_prelude() -> Self {
+        let mut globals = Globals::new();
+        native::define_builtins(&mut globals);
+        Self {
+            globals,
+            allow_forward_references: false,
+            cps: false,
+            namespace: Namespace::top_level(Binding::Late),
+            modules: Modules::new(evaluator::eval, false),
+            directory: PathBuf::new(),
+        }
+    }
+
+    /// Lets definitions refer to globals that are only defined later.
+    pub fn allow_forward_references(mut self, allow: bool) -> Self {
+        self.allow_forward_references = allow;
+        self.forget_modules();
+        self
+    }
+
+    /// Evaluates in continuation-passing style, which makes `callcc`
+    /// available, and lets deep recursion run without overflowing the stack.
+    /// `cps(false)` goes back to the direct evaluator and removes `callcc`.
+    ///
+    /// ```
+    /// # use lambda_calculus::Interpreter;
+    /// let mut interpreter = Interpreter::new().cps(true);
+    /// assert_eq!(interpreter.eval_as::<i64>(r"callcc \k -> plus +1 (k +2)").unwrap(), 2);
+    /// ```
+    pub fn cps(mut self, cps: bool) -> Self {
+        self.cps = cps;
+        if cps {
+            cps::define_callcc(&mut self.globals);
+        } else {
+            cps::undefine_callcc(&mut self.globals);
+        }
+        self.forget_modules();
+        self
+    }
+
+    /// Defines the natives of [`native::define_io`], which let programs print
+    /// and read, or with `false` removes them.
+    pub fn allow_io(mut self, allow: bool) -> Self {
+        if allow {
+            native::define_io(&mut self.globals);
+        } else {
+            native::undefine_io(&mut self.globals);
+        }
+        self
+    }
+
+    /// Has programs print to and read from `console` rather than standard
+    /// output and input.
+    ///
+    /// ```
+    /// # use std::{io, sync::{Arc, Mutex}};
+    /// # use lambda_calculus::{Console, Interpreter};
+    /// struct Recorder(Arc<Mutex<String>>);
+    ///
+    /// impl Console for Recorder {
+    ///     fn write(&self, text: &str) -> io::Result<()> {
+    ///         self.0.lock().unwrap().push_str(text);
+    ///         Ok(())
+    ///     }
+    ///
+    ///     fn read_line(&self) -> io::Result<Option<String>> {
+    ///         Ok(Some("world".to_string()))
+    ///     }
+    /// }
+    ///
+    /// let printed = Arc::new(Mutex::new(String::new()));
+    /// let mut interpreter = Interpreter::new()
+    ///     .allow_io(true)
+    ///     .console(Recorder(printed.clone()));
+    /// interpreter.eval_str(r#"println (readline "Who? ")"#).unwrap();
+    /// assert_eq!(*printed.lock().unwrap(), "Who? world\n");
+    /// ```
+    pub fn console(mut self, console: impl Console + 'static) -> Self {
+        self.globals.set_console(console);
+        self
+    }
+
+    /// Chooses whether definitions see later redefinitions of the globals they
+    /// use, as they do by default, or keep the values from when they were made.
+    /// Globals defined before keep the binding they were defined with.
+    ///
+    /// ```
+    /// # use lambda_calculus::{Binding, Interpreter};
+    /// let mut interpreter = Interpreter::new().binding(Binding::Snapshot);
+    /// interpreter.eval_str("let offset = +1").unwrap();
+    /// interpreter.eval_str(r"let shift = \x -> plus x offset").unwrap();
+    /// interpreter.eval_str("let offset = +10").unwrap();
+    /// assert_eq!(interpreter.eval_as::<i64>("shift offset").unwrap(), 11);
+    /// ```
+    pub fn binding(mut self, binding: Binding) -> Self {
+        self.namespace.set_binding(binding);
+        self
+    }
+
+    /// Runs a single statement, returning the value of the expression or, for
+    /// a `let`, the value just defined. An `import` gives the name of the
+    /// module, which its globals are prefixed with, and a `test` that passes
+    /// the value it checked.
+    ///
+    /// ```
+    /// # use lambda_calculus::Interpreter;
+    /// let mut interpreter = Interpreter::new();
+    /// let value = interpreter.eval_str("let answer = plus +40 +2").unwrap();
+    /// assert_eq!(value.to_string(), "42");
+    /// assert!(interpreter.eval_str("answer answer").is_err());
+    /// ```
+    pub fn eval_str(&mut self, source: &str) -> Result<RTValue, Error> {
+        let stmt = parser::parse_stmt(source)?;
+        Ok(self
+            .run_stmt(&stmt, &Source::new("<input>", source))?
+            .into_value())
+    }
+
+    /// Evaluates an expression without defining anything. It only borrows the
+    /// interpreter, so that with the `sync` feature one interpreter holding a
+    /// prelude can serve many threads at once.
+    ///
+    /// ```
+    /// # use lambda_calculus::Interpreter;
+    /// let interpreter = Interpreter::new();
+    /// assert_eq!(interpreter.evaluate("plus +1 +2").unwrap().to_string(), "3");
+    /// assert!(interpreter.evaluate("let x = +1").is_err());
+    /// ```
+    pub fn evaluate(&self, source: &str) -> Result<RTValue, Error> {
+        let expr = parser::parse_expr(source)?;
+        self.evaluate_expr(&expr, &Source::new("<input>", source))
+    }
+
+    /// Runs a single statement and converts its value.
+    pub fn eval_as<T: FromRTValue>(&mut self, source: &str) -> Result<T, Error> {
+        let value = self.eval_str(source)?;
+        Ok(T::from_rt_value(value)?)
+    }
+
+    /// Runs every statement in a file, in order, stopping at the first error.
+    /// Imports in the file are relative to it.
+    pub fn load_file(&mut self, path: impl AsRef<Path>) -> Result<(), LoadError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(LoadError::Read)?;
+        let directory = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        let directory = mem::replace(&mut self.directory, directory);
+        let result = self.load_source(Source::new(path.display().to_string(), source));
+        self.directory = directory;
+        result.map_err(LoadError::Run)
+    }
+
+    /// Runs every statement in `source`, in order, stopping at the first error.
+    pub fn load_str(&mut self, source: &str) -> Result<(), Error> {
+        self.load_source(Source::new("<input>", source))
+    }
+
+    fn load_source(&mut self, source: Shared<Source>) -> Result<(), Error> {
+        for stmt in parser::parse_program(&source.text)? {
+            self.run_stmt(&stmt, &source)?;
+        }
+        Ok(())
+    }
+
+    /// Defines a global, replacing any previous definition.
+    pub fn define(&mut self, name: &str, value: RTValue) {
+        let key = self.namespace.key_to_define(name, &self.globals);
+        self.namespace.define(name, &key);
+        self.globals.define(&key, value);
+    }
+
+    /// The value of a global as a program would see it, e.g. `m.square` for
+    /// something imported with `import math as m`.
+    pub fn lookup(&self, name: &str) -> Option<RTValue> {
+        self.globals.lookup(&self.namespace.resolve(name).ok()?)
+    }
+
+    /// Defines a native function of one argument.
+    ///
+    /// ```
+    /// # use lambda_calculus::Interpreter;
+    /// let mut interpreter = Interpreter::new();
+    /// interpreter.register_native1("negate", |x: i64| -x);
+    /// assert_eq!(interpreter.eval_as::<i64>("negate +3").unwrap(), -3);
+    /// ```
+    pub fn register_native1<A, M>(
+        &mut self,
+        name: &str,
+        native: impl Native1<A, M> + ThreadSafe + 'static,
+    ) where
+        A: FromRTValue,
+    {
+        self.globals.define_native1(name, native);
+    }
+
+    /// Defines a native function of two arguments, curried.
+    pub fn register_native2<A, B, M>(
+        &mut self,
+        name: &str,
+        native: impl Native2<A, B, M> + ThreadSafe + 'static,
+    ) where
+        A: FromRTValue,
+        B: FromRTValue,
+    {
+        self.globals.define_native2(name, native);
+    }
+
+    /// Defines a native function of three arguments, curried.
+    pub fn register_native3<A, B, C, M>(
+        &mut self,
+        name: &str,
+        native: impl Native3<A, B, C, M> + ThreadSafe + 'static,
+    ) where
+        A: FromRTValue,
+        B: FromRTValue,
+        C: FromRTValue,
+    {
+        self.globals.define_native3(name, native);
+    }
+
+    /// Reads a value as the Church numeral or boolean it encodes, if any.
+    ///
+    /// ```
+    /// # use lambda_calculus::{Decoded, Interpreter};
+    /// let mut interpreter = Interpreter::new();
+    /// let six = interpreter.eval_str("mul two three").unwrap();
+    /// assert_eq!(interpreter.decode_church(&six), Some(Decoded::Numeral(6)));
+    /// ```
+    pub fn decode_church(&self, value: &RTValue) -> Option<Decoded> {
+        church::decode(value, &self.globals)
+    }
+
+    /// Runs a single statement parsed from `source`, telling what it did.
+    pub(crate) fn run_stmt(
+        &mut self,
+        stmt: &parse_tree::Stmt,
+        source: &Shared<Source>,
+    ) -> Result<Outcome, Error> {
+        match stmt {
+            parse_tree::Stmt::Decl(decl) => {
+                let (key, expr) = self.namespace.prepare_definition(
+                    &decl.identifier,
+                    &decl.expr,
+                    source,
+                    &self.globals,
+                )?;
+                let options = BuildOptions {
+                    defining: Some(&key),
+                    allow_forward_references: self.allow_forward_references,
+                    namespace: Some(&self.namespace),
+                };
+                let value = self.eval(&expr, options)?;
+                let replaced = self
+                    .namespace
+                    .resolve(&decl.identifier)
+                    .ok()
+                    .filter(|replaced| self.globals.lookup(replaced).is_some());
+                self.namespace.define(&decl.identifier, &key);
+                self.globals.define(&key, value.clone());
+                Ok(Outcome::Defined {
+                    value,
+                    dependencies: expr.free_names().into_iter().map(String::from).collect(),
+                    key,
+                    replaced,
+                })
+            }
+            parse_tree::Stmt::Expr(expr) => Ok(Outcome::Value(self.evaluate_expr(expr, source)?)),
+            parse_tree::Stmt::Test(test) => {
+                let actual = self.evaluate_expr(&test.expr, source)?;
+                let expected = self.evaluate_expr(&test.expected, source)?;
+                testing::check(test, &actual, &expected, &self.globals)?;
+                Ok(Outcome::Value(actual))
+            }
+            parse_tree::Stmt::Import(import) => {
+                let module = self.modules.import(
+                    import,
+                    &self.directory,
+                    &mut self.namespace,
+                    &mut self.globals,
+                )?;
+                Ok(Outcome::Value(RTValue::String(Shared::from(
+                    module.name.as_str(),
+                ))))
+            }
+            parse_tree::Stmt::Export(export) => Err(ModuleError {
+                kind: ModuleErrorKind::ExportOutsideModule,
+                span: export.span.clone(),
+            })?,
+        }
+    }
+
+    pub(crate) fn evaluate_expr(
+        &self,
+        expr: &parse_tree::Expr,
+        source: &Shared<Source>,
+    ) -> Result<RTValue, Error> {
+        let options = BuildOptions {
+            defining: None,
+            allow_forward_references: self.allow_forward_references,
+            namespace: Some(&self.namespace),
+        };
+        self.eval(&self.namespace.prepare(expr, source)?, options)
+    }
+
+    fn eval(&self, expr: &Expr, options: BuildOptions) -> Result<RTValue, Error> {
+        if self.cps {
+            cps::eval(expr, &self.globals, options)
+        } else {
+            evaluator::eval(expr, &self.globals, options)
+        }
+    }
+
+    /// The global `name` refers to, as [`Interpreter::lookup`] finds it.
+    pub(crate) fn resolve(&self, name: &str) -> Option<String> {
+        self.namespace.resolve(name).ok()
+    }
+
+    /// The globals by the names they are used with, sorted. Previous versions
+    /// of redefined globals, and definitions of modules that can only be seen
+    /// through their exports, are left out.
+    pub(crate) fn visible_globals(&self) -> Vec<(String, RTValue)> {
+        self.namespace
+            .visible_names(&self.globals)
+            .into_iter()
+            .filter_map(|name| {
+                let value = self.lookup(&name)?;
+                Some((name, value))
+            })
+            .collect()
+    }
+
+    /// Shows `value` as its `Display` does, except that the globals it uses
+    /// which were redefined since are written with their version, e.g. `a@1`.
+    pub(crate) fn show(&self, value: &RTValue) -> String {
+        readback::value_with_versions(value, &|key| {
+            let name = modules::written_name(key);
+            matches!(
+                self.namespace.resolve(name),
+                Ok(current) if current != key && modules::written_name(&current) == name
+            )
+        })
+    }
+
+    /// Makes imports run the files they import again, in case they changed.
+    pub(crate) fn forget_modules(&mut self) {
+        let eval: modules::Eval = if self.cps { cps::eval } else { evaluator::eval };
+        self.modules = Modules::new(eval, self.allow_forward_references);
+    }
+
+    /// Makes imports relative to `directory` from now on, giving back the
+    /// directory they were relative to.
+    pub(crate) fn enter_directory(&mut self, directory: PathBuf) -> PathBuf {
+        mem::replace(&mut self.directory, directory)
+    }
+
+    pub(crate) fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Sets whether definitions may refer to globals defined later, giving
+    /// back whether they could.
+    pub(crate) fn set_allow_forward_references(&mut self, allow: bool) -> bool {
+        mem::replace(&mut self.allow_forward_references, allow)
+    }
+}
+
+/// What running a statement did.
+pub(crate) enum Outcome {
+    /// A `let` defined the global `key`. The name referred to the global
+    /// `replaced` before, if it referred to any, and the definition refers to
+    /// the globals in `dependencies`.
+    Defined {
+        value: RTValue,
+        key: String,
+        replaced: Option<String>,
+        dependencies: Vec<String>,
+    },
+    /// The value of an expression, the value a `test` checked, or the name of
+    /// an imported module.
+    Value(RTValue),
+}
+
+impl Outcome {
+    pub(crate) fn into_value(self) -> RTValue {
+        match self {
+            Outcome::Defined { value, .. } | Outcome::Value(value) => value,
+        }
+    }
+}
+
+/// Why a file could not be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    Read(io::Error),
+    Run(Error),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Read(error) => write!(f, "Could not read file: {}", error),
+            LoadError::Run(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Read(error) => Some(error),
+            LoadError::Run(error) => Some(error),
+        }
+    }
+}
@@ -4,5 +4,17 @@
 #![feature(pattern)]
 
 pub mod cli;
-mod pipeline;
+mod interpreter;
 mod parsers;
+mod pipeline;
+#[cfg(test)]
+mod test_support;
+
+pub use interpreter::{Interpreter, LoadError};
+pub use pipeline::church::Decoded;
+pub use pipeline::errors;
+pub use pipeline::modules::Binding;
+pub use pipeline::native::{
+    Console, FromRTValue, IntoRTValue, Native1, Native2, Native3, Pure, Stdio, WithGlobals,
+};
+pub use pipeline::runtime::{Globals, RTFunction, RTRef, RTValue, ThreadSafe};
@@ -1,13 +1,15 @@
-use std::{process, io, error::Error};
+use std::{process, io};
 
 use lambda_calculus::cli;
 
-fn main() -> Result<(), Box<dyn Error>> {
+fn main() {
     let config = cli::Config::from_env().unwrap_or_else(|error| {
         eprintln!("{}\n", error);
         cli::print_usage(&mut io::stderr()).unwrap();
         process::exit(1)
     });
-    cli::run(&config)?;
-    Ok(())
+    if let Err(error) = cli::run(&config) {
+        eprintln!("{}", error);
+        process::exit(1)
+    }
 }
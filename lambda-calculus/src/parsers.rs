@@ -1,5 +1,5 @@
 mod internals;
-pub use internals::{Parser, ParserState, ParserSpan, ParserPos};
+pub use internals::{Failure, Parser, ParserState, ParserSpan, ParserPos};
 
 pub mod adapters;
 pub mod delim;
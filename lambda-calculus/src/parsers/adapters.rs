@@ -205,7 +205,7 @@ pub mod looking_ahead {
 }
 
 pub mod falling_back {
-    use super::super::internals::{Parser, ParserState};
+    use super::super::internals::{Combine, Parser, ParserState};
 
     #[derive(Copy, Clone)]
     pub struct FallingBack<P, P2> {
@@ -225,6 +225,7 @@ pub mod falling_back {
     impl<P, P2> Parser for FallingBack<P, P2>
     where
         P: Parser,
+        P::ParseError: Combine,
         P2: Parser<Item = P::Item, ParseError = P::ParseError>,
     {
         type Item = P::Item;
@@ -238,7 +239,9 @@ pub mod falling_back {
                 Err(err) => {
                     if state.consumed_so_far() == previously_consumed {
                         // main_parser did not consume any data, so we fallback
-                        self.fallback_parser.parse(state)
+                        self.fallback_parser
+                            .parse(state)
+                            .map_err(|fallback_err| err.combine(fallback_err))
                     } else {
                         Err(err)
                     }
@@ -399,6 +402,45 @@ pub mod many {
     }
 }
 
+pub mod committed_many {
+    use super::super::internals::{Parser, ParserState};
+
+    /// Like [`super::many::Many`], but an item that fails after consuming some
+    /// input fails the whole series instead of ending it, so that errors inside
+    /// an item are not hidden behind whatever comes after the series.
+    #[derive(Copy, Clone)]
+    pub struct CommittedMany<P> {
+        parser: P,
+    }
+
+    impl<P> CommittedMany<P> {
+        pub fn new(parser: P) -> Self {
+            Self { parser }
+        }
+    }
+
+    impl<P> Parser for CommittedMany<P>
+    where
+        P: Parser,
+    {
+        type Item = Vec<P::Item>;
+
+        type ParseError = P::ParseError;
+
+        fn parse(&self, state: &mut ParserState) -> Result<Self::Item, Self::ParseError> {
+            let mut results: Vec<P::Item> = vec![];
+            loop {
+                let previously_consumed = state.consumed_so_far();
+                match self.parser.parse(state) {
+                    Ok(item) => results.push(item),
+                    Err(err) if state.consumed_so_far() != previously_consumed => return Err(err),
+                    Err(_) => return Ok(results),
+                }
+            }
+        }
+    }
+}
+
 pub mod skip_many {
     use std::marker::PhantomData;
 
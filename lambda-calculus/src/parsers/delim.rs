@@ -1,6 +1,6 @@
 #![allow(dead_code)]
 
-use super::internals::{Parser, ParserState};
+use super::internals::{Failure, Parser, ParserState};
 use super::one_char;
 
 pub fn whitespace() -> one_char::OneCharMatches<fn(char) -> bool, &'static str> {
@@ -14,15 +14,12 @@ pub const EXPECT_END: ExpectEnd = ExpectEnd {};
 
 impl Parser for ExpectEnd {
     type Item = ();
-    type ParseError = String;
+    type ParseError = Failure;
     fn parse(&self, state: &mut ParserState) -> Result<Self::Item, Self::ParseError> {
         if state.leftovers().is_empty() {
             Ok(())
         } else {
-            Err(format!(
-                "Expected end of input, but found {}",
-                &state.leftovers()[..10.min(state.leftovers().len())]
-            ))?
+            Err(Failure::at(state, "end of input", 10))
         }
     }
 }
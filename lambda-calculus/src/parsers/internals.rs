@@ -3,13 +3,13 @@
 use std::{error::Error, ops::Deref};
 
 use super::adapters::{
-    also::Also, at_least_one::AtLeastOne, backtracking::Backtracking, falling_back::FallingBack,
-    looking_ahead::LookingAhead, many::Many, map::Map, map_err::MapErr, optional::Optional,
-    paired_with::PairedWith, skip_many::SkipMany, then::Then, validate::Validate,
-    with_span::WithSpan,
+    also::Also, at_least_one::AtLeastOne, backtracking::Backtracking,
+    committed_many::CommittedMany, falling_back::FallingBack, looking_ahead::LookingAhead,
+    many::Many, map::Map, map_err::MapErr, optional::Optional, paired_with::PairedWith,
+    skip_many::SkipMany, then::Then, validate::Validate, with_span::WithSpan,
 };
 
-#[derive(Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ParserPos {
     pub line: usize,
     pub column: usize,
@@ -32,10 +32,16 @@ pub struct ParserState<'a> {
 
 impl<'a> ParserState<'a> {
     pub fn new(text: &str) -> ParserState {
+        ParserState::starting_at(text, ParserPos::new(0, 0))
+    }
+
+    /// Starts parsing `text` as if it were found at `position` of a larger
+    /// source, so that reported positions refer to that source.
+    pub fn starting_at(text: &'a str, position: ParserPos) -> Self {
         ParserState {
             leftovers: text,
             consumed_so_far: 0,
-            position: ParserPos::new(0, 0),
+            position,
         }
     }
 
@@ -66,6 +72,79 @@ impl<'a> ParserState<'a> {
     }
 }
 
+/// Why a parser could not make progress: what it was looking for, and what it
+/// found instead (`None` meaning it reached the end of the input).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub span: ParserSpan,
+    pub expected: Vec<String>,
+    pub found: Option<String>,
+}
+
+impl Failure {
+    /// Fails at the current position of `state`, spanning the next
+    /// `found_chars` characters of the current line as what was found.
+    pub fn at(state: &ParserState, expected: impl Into<String>, found_chars: usize) -> Self {
+        let start = state.current_position().clone();
+        let found: String = state
+            .leftovers()
+            .chars()
+            .take_while(|c| *c != '\n')
+            .take(found_chars.max(1))
+            .collect();
+        let mut end_state = state.clone();
+        end_state.advance(found.len());
+        Self {
+            span: (start, end_state.current_position().clone()),
+            expected: vec![expected.into()],
+            found: if state.leftovers().is_empty() {
+                None
+            } else {
+                Some(found)
+            },
+        }
+    }
+}
+
+/// Errors that can be combined when two alternative parsers both fail at the
+/// same point.
+pub trait Combine {
+    fn combine(self, other: Self) -> Self;
+}
+
+impl Combine for Failure {
+    fn combine(mut self, other: Self) -> Self {
+        let position = |failure: &Failure| {
+            let (start, _) = &failure.span;
+            (start.line, start.column)
+        };
+        match position(&self).cmp(&position(&other)) {
+            std::cmp::Ordering::Less => other,
+            std::cmp::Ordering::Greater => self,
+            std::cmp::Ordering::Equal => {
+                for expected in other.expected {
+                    if !self.expected.contains(&expected) {
+                        self.expected.push(expected);
+                    }
+                }
+                self
+            }
+        }
+    }
+}
+
+impl Combine for String {
+    fn combine(self, other: Self) -> Self {
+        other
+    }
+}
+
+impl Combine for ! {
+    fn combine(self, _: Self) -> Self {
+        self
+    }
+}
+
 pub trait Parser {
     type Item;
     type ParseError = Box<dyn Error>;
@@ -166,6 +245,13 @@ pub trait Parser {
         Many::new(self)
     }
 
+    fn committed_many(self) -> CommittedMany<Self>
+    where
+        Self: std::marker::Sized,
+    {
+        CommittedMany::new(self)
+    }
+
     fn skip_many<E>(self) -> SkipMany<Self, E>
     where
         Self: std::marker::Sized,
@@ -2,7 +2,7 @@
 
 use std::fmt::Display;
 
-use super::internals::{Parser, ParserState};
+use super::internals::{Failure, Parser, ParserState};
 
 #[derive(Copy, Clone)]
 pub struct OneCharMatches<Pred, Desc> {
@@ -23,24 +23,14 @@ where
     Desc: Display,
 {
     type Item = char;
-    type ParseError = String;
+    type ParseError = Failure;
     fn parse(&self, state: &mut ParserState) -> Result<Self::Item, Self::ParseError> {
         match state.leftovers().chars().next() {
-            Some(c) => {
-                if (self.predicate)(c) {
-                    state.advance(c.len_utf8());
-                    Ok(c)
-                } else {
-                    Err(format!(
-                        "Unexpected char {}. Expected {}",
-                        c, self.description
-                    ))?
-                }
+            Some(c) if (self.predicate)(c) => {
+                state.advance(c.len_utf8());
+                Ok(c)
             }
-            None => Err(format!(
-                "Unexpected end of input. Expected {}",
-                self.description
-            ))?,
+            _ => Err(Failure::at(state, self.description.to_string(), 1)),
         }
     }
 }
@@ -52,14 +42,14 @@ pub const ANY: AnyChar = AnyChar {};
 
 impl Parser for AnyChar {
     type Item = char;
-    type ParseError = String;
+    type ParseError = Failure;
     fn parse(&self, state: &mut ParserState) -> Result<Self::Item, Self::ParseError> {
         match state.leftovers().chars().next() {
             Some(c) => {
                 state.advance(c.len_utf8());
                 Ok(c)
             }
-            None => Err("Unexpected end of input.")?,
+            None => Err(Failure::at(state, "any character", 1)),
         }
     }
 }
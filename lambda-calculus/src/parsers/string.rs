@@ -4,7 +4,7 @@ use std::marker::PhantomData;
 
 use derivative::Derivative;
 
-use super::internals::{Parser, ParserState};
+use super::internals::{Failure, Parser, ParserState};
 
 #[derive(Derivative)]
 #[derivative(Copy, Clone)]
@@ -64,7 +64,7 @@ pub fn expect(expected: &str) -> Expect {
 impl<'a> Parser for Expect<'a> {
     type Item = ();
 
-    type ParseError = String;
+    type ParseError = Failure;
 
     fn parse(&self, state: &mut ParserState) -> Result<Self::Item, Self::ParseError> {
         match check::<!>(self.expected).parse(state) {
@@ -72,10 +72,10 @@ impl<'a> Parser for Expect<'a> {
                 state.advance(self.expected.len());
                 Ok(())
             }
-            Ok(false) => Err(format!(
-                "Expected\n  {}\nbut found\n  {}",
-                self.expected,
-                &state.leftovers()[..self.expected.len().min(state.leftovers().len())]
+            Ok(false) => Err(Failure::at(
+                state,
+                format!("`{}`", self.expected),
+                self.expected.chars().count(),
             )),
             Err(_) => unreachable!(),
         }
@@ -94,7 +94,7 @@ pub fn expect_owned(expected: String) -> ExpectOwned {
 impl Parser for ExpectOwned {
     type Item = ();
 
-    type ParseError = String;
+    type ParseError = Failure;
 
     fn parse(&self, state: &mut ParserState) -> Result<Self::Item, Self::ParseError> {
         expect(&self.expected).parse(state)
@@ -4,3 +4,13 @@ pub mod locally_nameless_tree;
 pub mod parse_to_locally_nameless;
 pub mod runtime;
 pub mod evaluator;
+pub mod cps;
+pub mod modules;
+pub mod errors;
+pub mod diagnostics;
+pub mod printer;
+pub mod readback;
+pub mod church;
+pub mod native;
+pub mod prelude;
+pub mod testing;
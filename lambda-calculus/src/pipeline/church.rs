@@ -0,0 +1,307 @@
+use super::{
+    locally_nameless_tree::{BoundVar, Expr, FreeVar, Lookup},
+    runtime::{FunctionOrigin, Globals, Locals, RTFunction, RTValue},
+};
+
+/// What a function value turned out to encode once normalized.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Decoded {
+    Numeral(u64),
+    Boolean(bool),
+    /// `\f -> \x -> x` is both zero and false.
+    ZeroOrFalse,
+}
+
+impl std::fmt::Display for Decoded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Decoded::Numeral(n) => write!(f, "{} (church)", n),
+            Decoded::Boolean(b) => write!(f, "{} (church)", b),
+            Decoded::ZeroOrFalse => f.write_str("0 or false (church)"),
+        }
+    }
+}
+
+/// How many nodes normalizing may build in all, and how deeply it may recurse,
+/// before giving up, so that values without a normal form, like `y`, are
+/// given up on quickly and without running out of stack. Numerals past about
+/// a thousand are not recognized.
+const MAX_WORK: usize = 200_000;
+const MAX_DEPTH: usize = 1_000;
+
+/// Tries to read `value` as a Church numeral (`\f -> \x -> f (f x)` is 2) or
+/// a Church boolean (`\t -> \f -> t` is true), by reading it back as a lambda
+/// term and reducing that to normal form. This never runs the function:
+/// anything that is not a plain lambda term, like a native, a number or a
+/// `try`, is not decoded.
+pub fn decode(value: &RTValue, globals: &Globals) -> Option<Decoded> {
+    let RTValue::Function(function) = value else {
+        return None;
+    };
+    let mut normalizer = Normalizer {
+        globals,
+        work_left: MAX_WORK,
+    };
+    let term = normalizer.function(function, 0)?;
+    // `\f -> \x -> term f x`, so that `\f -> f` is one too
+    let applied = Term::lambda(Term::lambda(Term::apply(
+        Term::apply(term, Term::Var(1)),
+        Term::Var(0),
+    )));
+    match normalizer.normalize(applied, 0)? {
+        Term::Lambda(body) => match *body {
+            Term::Lambda(body) => read(&body),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// What the body of `\f -> \x -> body` in normal form encodes.
+fn read(mut body: &Term) -> Option<Decoded> {
+    if let Term::Var(1) = body {
+        return Some(Decoded::Boolean(true));
+    }
+    let mut count = 0;
+    loop {
+        match body {
+            Term::Var(0) if count == 0 => return Some(Decoded::ZeroOrFalse),
+            Term::Var(0) => return Some(Decoded::Numeral(count)),
+            Term::Apply(function, argument) if matches!(**function, Term::Var(1)) => {
+                count += 1;
+                body = argument;
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// A lambda term with de Bruijn indices, and globals that are looked up when
+/// they are applied.
+enum Term {
+    Var(usize),
+    Global(String),
+    Lambda(Box<Term>),
+    Apply(Box<Term>, Box<Term>),
+}
+
+impl Term {
+    fn lambda(body: Term) -> Self {
+        Term::Lambda(Box::new(body))
+    }
+
+    fn apply(function: Term, argument: Term) -> Self {
+        Term::Apply(Box::new(function), Box::new(argument))
+    }
+}
+
+struct Normalizer<'a> {
+    globals: &'a Globals,
+    work_left: usize,
+}
+
+impl Normalizer<'_> {
+    fn spend(&mut self) -> Option<()> {
+        self.work_left = self.work_left.checked_sub(1)?;
+        Some(())
+    }
+
+    fn deeper(level: usize) -> Option<usize> {
+        (level < MAX_DEPTH).then_some(level + 1)
+    }
+
+    fn value(&mut self, value: &RTValue, level: usize) -> Option<Term> {
+        match value {
+            RTValue::Function(function) => self.function(function, level),
+            _ => None,
+        }
+    }
+
+    fn function(&mut self, function: &RTFunction, level: usize) -> Option<Term> {
+        match &function.origin {
+            FunctionOrigin::Lambda { body, captured } => {
+                Some(Term::lambda(self.expr(body, 1, captured, level)?))
+            }
+            _ => None,
+        }
+    }
+
+    /// `expr` under `depth` parameters, with the values of `captured` in place
+    /// of the variables that refer to them.
+    fn expr(&mut self, expr: &Expr, depth: usize, captured: &Locals, level: usize) -> Option<Term> {
+        let level = Self::deeper(level)?;
+        self.spend()?;
+        match expr {
+            Expr::Lambda(lambda) => Some(Term::lambda(self.expr(
+                &lambda.body,
+                depth + 1,
+                captured,
+                level,
+            )?)),
+            Expr::Apply(apply) => Some(Term::apply(
+                self.expr(&apply.function, depth, captured, level)?,
+                self.expr(&apply.argument, depth, captured, level)?,
+            )),
+            Expr::Lookup(Lookup::Free(FreeVar { name, .. })) => Some(Term::Global(name.clone())),
+            Expr::Lookup(Lookup::Bound(BoundVar { de_brujn_index, .. })) => {
+                if *de_brujn_index < depth {
+                    Some(Term::Var(*de_brujn_index))
+                } else {
+                    // captured values are closed, so need no shifting
+                    self.value(&captured.lookup(de_brujn_index - depth)?, level)
+                }
+            }
+            Expr::LitInteger(_) | Expr::LitString(_) | Expr::TryCatch(_) | Expr::Sequence(_) => {
+                None
+            }
+        }
+    }
+
+    /// Reduces `term` to normal form, reducing the leftmost outermost redex
+    /// first, so that it finds the normal form whenever there is one.
+    fn normalize(&mut self, term: Term, level: usize) -> Option<Term> {
+        let level = Self::deeper(level)?;
+        Some(match self.head_normalize(term, level)? {
+            Term::Lambda(body) => Term::lambda(self.normalize(*body, level)?),
+            Term::Apply(function, argument) => Term::apply(
+                self.normalize(*function, level)?,
+                self.normalize(*argument, level)?,
+            ),
+            other => other,
+        })
+    }
+
+    /// Reduces `term` until it is a lambda, or a variable applied to
+    /// arguments.
+    fn head_normalize(&mut self, mut term: Term, level: usize) -> Option<Term> {
+        loop {
+            self.spend()?;
+            term = match term {
+                Term::Apply(function, argument) => {
+                    match self.head_normalize(*function, Self::deeper(level)?)? {
+                        Term::Lambda(body) => self.substitute(*body, 0, &argument, level)?,
+                        function => return Some(Term::Apply(Box::new(function), argument)),
+                    }
+                }
+                Term::Global(name) => self.value(&self.globals.lookup(&name)?, level)?,
+                other => return Some(other),
+            }
+        }
+    }
+
+    /// `body` with `argument` in place of the variable of index `index`, and
+    /// the variables bound outside of it one index closer.
+    fn substitute(
+        &mut self,
+        body: Term,
+        index: usize,
+        argument: &Term,
+        level: usize,
+    ) -> Option<Term> {
+        let level = Self::deeper(level)?;
+        self.spend()?;
+        Some(match body {
+            Term::Var(i) if i == index => self.shift(argument, index, 0, level)?,
+            Term::Var(i) if i > index => Term::Var(i - 1),
+            Term::Lambda(body) => {
+                Term::lambda(self.substitute(*body, index + 1, argument, level)?)
+            }
+            Term::Apply(function, arg) => Term::apply(
+                self.substitute(*function, index, argument, level)?,
+                self.substitute(*arg, index, argument, level)?,
+            ),
+            other => other,
+        })
+    }
+
+    /// A copy of `term` with the variables bound outside of it, those of index
+    /// `cutoff` or more, `by` indices further.
+    fn shift(&mut self, term: &Term, by: usize, cutoff: usize, level: usize) -> Option<Term> {
+        let level = Self::deeper(level)?;
+        self.spend()?;
+        Some(match term {
+            Term::Var(i) if *i >= cutoff => Term::Var(i + by),
+            Term::Var(i) => Term::Var(*i),
+            Term::Global(name) => Term::Global(name.clone()),
+            Term::Lambda(body) => Term::lambda(self.shift(body, by, cutoff + 1, level)?),
+            Term::Apply(function, argument) => Term::apply(
+                self.shift(function, by, cutoff, level)?,
+                self.shift(argument, by, cutoff, level)?,
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::eval_in;
+
+    fn decode_str(text: &str) -> Option<Decoded> {
+        decode_in(&Globals::new(), text)
+    }
+
+    fn decode_in(globals: &Globals, text: &str) -> Option<Decoded> {
+        decode(&eval_in(globals, text), globals)
+    }
+
+    #[test]
+    fn decodes_numerals() {
+        assert_eq!(
+            decode_str(r"\f -> \x -> f (f x)"),
+            Some(Decoded::Numeral(2))
+        );
+        assert_eq!(
+            decode_str(r"(\n -> \f -> \x -> f (n f x)) (\f -> \x -> f x)"),
+            Some(Decoded::Numeral(2))
+        );
+        assert_eq!(decode_str(r"\f -> \x -> x"), Some(Decoded::ZeroOrFalse));
+        assert_eq!(decode_str(r"\f -> f"), Some(Decoded::Numeral(1)));
+    }
+
+    #[test]
+    fn decodes_booleans() {
+        assert_eq!(decode_str(r"\t -> \f -> t"), Some(Decoded::Boolean(true)));
+        assert_eq!(
+            decode_str(r"(\b -> b (\t -> \f -> f) (\t -> \f -> t)) (\t -> \f -> t)"),
+            Some(Decoded::ZeroOrFalse)
+        );
+    }
+
+    #[test]
+    fn leaves_other_values_alone() {
+        assert_eq!(decode_str(r"\x -> \y -> +3"), None);
+        assert_eq!(decode_str(r"\f -> \x -> f x x"), None);
+        assert_eq!(decode_str(r"+2"), None);
+    }
+
+    #[test]
+    fn gives_up_on_terms_without_normal_form() {
+        let y = r"\f -> (\x -> f (x x)) \x -> f (x x)";
+        assert_eq!(decode_str(y), None);
+        assert_eq!(decode_str(r"\f -> \x -> (\y -> y y) \y -> y y"), None);
+    }
+
+    #[test]
+    fn never_runs_natives() {
+        let mut globals = Globals::new();
+        globals.define(
+            "boom",
+            RTValue::Function(RTFunction::native("boom", 1, vec![], |_, _| {
+                panic!("decoding ran a native")
+            })),
+        );
+        let zero = eval_in(&globals, r"\f -> \x -> x");
+        globals.define("zero", zero);
+        assert_eq!(decode_in(&globals, r"\f -> \x -> boom x"), None);
+        // only the normal form counts, which leaves `boom` out
+        assert_eq!(
+            decode_in(&globals, r"\f -> \x -> (\u -> x) (boom f)"),
+            Some(Decoded::ZeroOrFalse)
+        );
+        assert_eq!(
+            decode_in(&globals, r"\f -> \x -> f (zero f x)"),
+            Some(Decoded::Numeral(1))
+        );
+    }
+}
@@ -0,0 +1,506 @@
+use std::collections::HashMap;
+
+use super::{
+    errors::{Error, EvalError, NativeError, RuntimeError, SourceSpan},
+    evaluator::{self, BuildOptions},
+    locally_nameless_tree::{BoundVar, Expr, FreeVar, Lookup},
+    runtime::{FunctionOrigin, Globals, Locals, RTFunction, RTValue, Shared},
+};
+
+/// Evaluates `expr` in continuation-passing style: instead of returning through
+/// the Rust stack, every step hands its result to an explicit [`Continuation`],
+/// which `callcc` can capture as a value and resume any number of times.
+///
+/// Functions are applied by evaluating their bodies in the same way, wherever
+/// they were defined, so a continuation captured deep inside a call includes
+/// the callers. Natives are called as usual, and continuations captured while
+/// they run end where the native was called.
+pub fn eval(expr: &Expr, globals: &Globals, options: BuildOptions) -> Result<RTValue, Error> {
+    // building a direct-style closure reports unbound globals exactly as the
+    // default evaluator does
+    evaluator::build(expr, globals, options)?;
+    Ok(run(compile(expr), Locals::empty(), globals)?)
+}
+
+/// Defines `callcc`, which calls its argument with the current continuation.
+pub fn define_callcc(globals: &mut Globals) {
+    let callcc = RTFunction::new(FunctionOrigin::CallCC, |_, _| {
+        Err(NativeError::new("callcc needs the CPS evaluator"))?
+    });
+    globals.define("callcc", RTValue::Function(callcc));
+}
+
+/// Removes the `callcc` of [`define_callcc`], unless it was redefined since.
+pub fn undefine_callcc(globals: &mut Globals) {
+    if let Some(RTValue::Function(function)) = globals.lookup("callcc") {
+        if let FunctionOrigin::CallCC = function.origin {
+            globals.undefine("callcc");
+        }
+    }
+}
+
+/// An expression prepared for the CPS evaluator, with shared subtrees so that
+/// continuations can hold on to what is left to evaluate.
+enum Node {
+    Lambda {
+        /// The body as it will be shown and, for calls from natives, run.
+        source: Shared<Expr>,
+        body: Shared<Node>,
+    },
+    Apply {
+        function: Shared<Node>,
+        argument: Shared<Node>,
+        span: SourceSpan,
+    },
+    Bound {
+        de_brujn_index: usize,
+        span: SourceSpan,
+    },
+    Free {
+        name: String,
+        span: SourceSpan,
+    },
+    Literal(RTValue),
+    TryCatch {
+        body: Shared<Node>,
+        handler: Shared<Node>,
+        span: SourceSpan,
+    },
+    Sequence {
+        first: Shared<Node>,
+        second: Shared<Node>,
+    },
+}
+
+fn compile(expr: &Expr) -> Shared<Node> {
+    Shared::new(match expr {
+        Expr::Lambda(lambda) => Node::Lambda {
+            source: Shared::new(lambda.body.clone()),
+            body: compile(&lambda.body),
+        },
+        Expr::Apply(apply) => Node::Apply {
+            function: compile(&apply.function),
+            argument: compile(&apply.argument),
+            span: apply.span.clone(),
+        },
+        Expr::Lookup(Lookup::Bound(BoundVar {
+            de_brujn_index,
+            span,
+        })) => Node::Bound {
+            de_brujn_index: *de_brujn_index,
+            span: span.clone(),
+        },
+        Expr::Lookup(Lookup::Free(FreeVar { name, span })) => Node::Free {
+            name: name.clone(),
+            span: span.clone(),
+        },
+        Expr::LitInteger(i) => Node::Literal(RTValue::Integer(*i)),
+        Expr::LitString(s) => Node::Literal(RTValue::String(Shared::from(&s[..]))),
+        Expr::TryCatch(try_catch) => Node::TryCatch {
+            body: compile(&try_catch.body),
+            handler: compile(&try_catch.handler),
+            span: try_catch.span.clone(),
+        },
+        Expr::Sequence(sequence) => Node::Sequence {
+            first: compile(&sequence.first),
+            second: compile(&sequence.second),
+        },
+    })
+}
+
+/// What is left to do with the value being computed, as a stack of frames
+/// that is never changed in place, so that it can be resumed more than once.
+#[derive(Clone)]
+pub struct Continuation(Option<Shared<Link>>);
+
+struct Link {
+    frame: Frame,
+    rest: Continuation,
+}
+
+#[derive(Clone)]
+enum Frame {
+    /// The value is a function; evaluate its argument next.
+    Argument {
+        argument: Shared<Node>,
+        locals: Locals,
+        span: SourceSpan,
+    },
+    /// The value is the argument to call `function` with.
+    Call { function: RTValue, span: SourceSpan },
+    /// The value is a handler, to call with the value of the error it caught.
+    Handle { error: RTValue, span: SourceSpan },
+    /// The value is dropped, and `second` evaluated instead.
+    Then {
+        second: Shared<Node>,
+        locals: Locals,
+    },
+    /// Errors raised before this frame is reached are given to `handler`.
+    Catch {
+        handler: Shared<Node>,
+        locals: Locals,
+        span: SourceSpan,
+    },
+}
+
+impl Continuation {
+    fn done() -> Self {
+        Self(None)
+    }
+
+    fn push(&self, frame: Frame) -> Self {
+        Self(Some(Shared::new(Link {
+            frame,
+            rest: self.clone(),
+        })))
+    }
+
+    fn pop(&self) -> Option<(Frame, Continuation)> {
+        self.0
+            .as_ref()
+            .map(|link| (link.frame.clone(), link.rest.clone()))
+    }
+
+    fn into_value(self) -> RTValue {
+        let function = RTFunction::new(FunctionOrigin::Continuation(self), |_, _| {
+            Err(NativeError::new(
+                "continuations can only be resumed by the CPS evaluator",
+            ))?
+        });
+        RTValue::Function(function)
+    }
+}
+
+enum State {
+    Eval(Shared<Node>, Locals, Continuation),
+    Apply {
+        function: RTValue,
+        argument: RTValue,
+        span: Option<SourceSpan>,
+        then: Continuation,
+    },
+    Return(RTValue, Continuation),
+    Fail(EvalError, Continuation),
+}
+
+fn run(node: Shared<Node>, locals: Locals, globals: &Globals) -> Result<RTValue, EvalError> {
+    let mut bodies = Bodies::default();
+    let mut state = State::Eval(node, locals, Continuation::done());
+    loop {
+        state = match state {
+            State::Eval(node, locals, then) => eval_node(&node, locals, then, globals, &mut bodies),
+            State::Return(value, then) => match then.pop() {
+                None => return Ok(value),
+                Some((frame, rest)) => return_to(frame, value, rest),
+            },
+            State::Fail(error, then) => match then.pop() {
+                None => return Err(error),
+                Some((
+                    Frame::Catch {
+                        handler,
+                        locals,
+                        span,
+                    },
+                    rest,
+                )) => State::Eval(
+                    handler,
+                    locals,
+                    rest.push(Frame::Handle {
+                        error: error.into_value(),
+                        span,
+                    }),
+                ),
+                Some((_, rest)) => State::Fail(error, rest),
+            },
+            State::Apply {
+                function,
+                argument,
+                span,
+                then,
+            } => apply(function, argument, span, then, globals, &mut bodies),
+        }
+    }
+}
+
+fn eval_node(
+    node: &Node,
+    locals: Locals,
+    then: Continuation,
+    globals: &Globals,
+    bodies: &mut Bodies,
+) -> State {
+    match node {
+        Node::Lambda { source, body } => {
+            bodies.remember(source, body);
+            State::Return(lambda(source, body, locals), then)
+        }
+        Node::Apply {
+            function,
+            argument,
+            span,
+        } => {
+            let then = then.push(Frame::Argument {
+                argument: argument.clone(),
+                locals: locals.clone(),
+                span: span.clone(),
+            });
+            State::Eval(function.clone(), locals, then)
+        }
+        Node::Bound {
+            de_brujn_index,
+            span,
+        } => match locals.lookup(*de_brujn_index) {
+            Some(value) => State::Return(value, then),
+            None => State::Fail(
+                EvalError::from(RuntimeError::UnboundLocal(*de_brujn_index)).at(span),
+                then,
+            ),
+        },
+        Node::Free { name, span } => match globals.lookup(name) {
+            Some(value) => State::Return(value, then),
+            None => State::Fail(
+                EvalError::from(RuntimeError::UnboundGlobal(name.clone())).at(span),
+                then,
+            ),
+        },
+        Node::Literal(value) => State::Return(value.clone(), then),
+        Node::TryCatch {
+            body,
+            handler,
+            span,
+        } => {
+            let then = then.push(Frame::Catch {
+                handler: handler.clone(),
+                locals: locals.clone(),
+                span: span.clone(),
+            });
+            State::Eval(body.clone(), locals, then)
+        }
+        Node::Sequence { first, second } => {
+            let then = then.push(Frame::Then {
+                second: second.clone(),
+                locals: locals.clone(),
+            });
+            State::Eval(first.clone(), locals, then)
+        }
+    }
+}
+
+fn return_to(frame: Frame, value: RTValue, rest: Continuation) -> State {
+    match frame {
+        Frame::Argument {
+            argument,
+            locals,
+            span,
+        } => match value {
+            RTValue::Function(_) => State::Eval(
+                argument,
+                locals,
+                rest.push(Frame::Call {
+                    function: value,
+                    span,
+                }),
+            ),
+            other => State::Fail(evaluator::not_a_function(&other).at(&span), rest),
+        },
+        Frame::Call { function, span } => State::Apply {
+            function,
+            argument: value,
+            span: Some(span),
+            then: rest,
+        },
+        Frame::Handle { error, span } => State::Apply {
+            function: value,
+            argument: error,
+            span: Some(span),
+            then: rest,
+        },
+        Frame::Then { second, locals } => State::Eval(second, locals, rest),
+        Frame::Catch { .. } => State::Return(value, rest),
+    }
+}
+
+fn apply(
+    function: RTValue,
+    argument: RTValue,
+    span: Option<SourceSpan>,
+    then: Continuation,
+    globals: &Globals,
+    bodies: &mut Bodies,
+) -> State {
+    let locate = |error: EvalError| match &span {
+        Some(span) => error.at(span),
+        None => error,
+    };
+    let function = match function {
+        RTValue::Function(function) => function,
+        other => return State::Fail(locate(evaluator::not_a_function(&other)), then),
+    };
+    match &function.origin {
+        FunctionOrigin::Lambda { body, captured } => {
+            State::Eval(bodies.get(body), captured.bind_local(argument), then)
+        }
+        FunctionOrigin::CallCC => State::Apply {
+            function: argument,
+            argument: then.clone().into_value(),
+            span,
+            then,
+        },
+        FunctionOrigin::Continuation(resume) => State::Return(argument, resume.clone()),
+        FunctionOrigin::Native { .. } => match function.call(argument, globals) {
+            Ok(value) => State::Return(value, then),
+            Err(error) => State::Fail(locate(error), then),
+        },
+    }
+}
+
+/// A lambda made by the CPS evaluator, which natives can call as well.
+fn lambda(source: &Shared<Expr>, body: &Shared<Node>, locals: Locals) -> RTValue {
+    let body = body.clone();
+    let captured = locals.clone();
+    RTValue::Function(RTFunction::lambda(
+        source.clone(),
+        captured,
+        move |arg, globals| run(body.clone(), locals.bind_local(arg), globals),
+    ))
+}
+
+/// Compiled bodies of the functions applied so far, by the address of their
+/// source. Each entry keeps its source alive, so the address is not reused.
+#[derive(Default)]
+struct Bodies(HashMap<usize, (Shared<Expr>, Shared<Node>)>);
+
+impl Bodies {
+    fn key(source: &Shared<Expr>) -> usize {
+        Shared::as_ptr(source) as usize
+    }
+
+    fn remember(&mut self, source: &Shared<Expr>, body: &Shared<Node>) {
+        self.0
+            .entry(Self::key(source))
+            .or_insert_with(|| (source.clone(), body.clone()));
+    }
+
+    fn get(&mut self, source: &Shared<Expr>) -> Shared<Node> {
+        self.0
+            .entry(Self::key(source))
+            .or_insert_with(|| (source.clone(), compile(source)))
+            .1
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::{native, prelude};
+    use crate::test_support::prepare;
+
+    fn eval_str(text: &str) -> Result<String, String> {
+        let mut globals = Globals::new();
+        native::define_builtins(&mut globals);
+        prelude::load(&mut globals).unwrap();
+        define_callcc(&mut globals);
+        eval(&prepare(text), &globals, BuildOptions::default())
+            .map(|value| value.to_string())
+            .map_err(|error| error.to_string())
+    }
+
+    #[test]
+    fn evaluates_like_the_direct_evaluator() {
+        assert_eq!(eval_str(r"(\x -> plus x x) +21"), Ok("42".into()));
+        assert_eq!(
+            eval_str(r"repeatedly +3 (\x -> plus x +2) +0"),
+            Ok("6".into())
+        );
+        assert_eq!(eval_str("mul two three (plus +1) +0"), Ok("6".into()));
+        assert_eq!(
+            eval_str(r"try div +1 +0 catch \e -> e"),
+            Ok("\"Division by zero\"".into())
+        );
+        assert_eq!(
+            eval_str(r"(\r -> set r +1; callcc (\k -> set r +2; k +0; set r +3); get r) (ref +0)"),
+            Ok("2".into())
+        );
+        assert_eq!(
+            eval_str(r"(\f -> f +1) (\x -> +2 x)"),
+            Err("Type error: Cannot call number 2 as a fn".into())
+        );
+    }
+
+    #[test]
+    fn locates_handlers_that_are_not_functions() {
+        let globals = Globals::new();
+        let error = eval(
+            &prepare(r"+1; try (\x -> x) +1 +2 catch +2"),
+            &globals,
+            BuildOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Type error: Cannot call number 2 as a fn"
+        );
+        let (start, end) = error.span().unwrap();
+        assert_eq!((start.column, end.column), (4, 32));
+    }
+
+    #[test]
+    fn escapes_through_continuations() {
+        assert_eq!(
+            eval_str(r"plus +1 (callcc \k -> plus +10 (k +2))"),
+            Ok("3".into())
+        );
+        // escaping out of a prelude function, skipping the rest of the fold
+        assert_eq!(
+            eval_str(
+                r"callcc \k -> foldr (\x -> \acc -> iszero x (k +0) (plus +1 acc)) +0 (cons one (cons zero nil))"
+            ),
+            Ok("0".into())
+        );
+        // leaving a try skips its handler
+        assert_eq!(
+            eval_str(r"callcc \k -> try k +1 catch \e -> +2"),
+            Ok("1".into())
+        );
+    }
+
+    #[test]
+    fn reenters_continuations_after_they_returned() {
+        // `k` first receives the continuation itself, then calls it again
+        // with a function, re-entering the application
+        assert_eq!(
+            eval_str(r"(\k -> k (\x -> +42)) (callcc \c -> c)"),
+            Ok("42".into())
+        );
+        // resuming inside a try brings its handler back...
+        assert_eq!(
+            eval_str(r"try (callcc \c -> c) (\x -> raise +5) catch \e -> plus e +100"),
+            Ok("105".into())
+        );
+        // ...until the try is left again
+        assert_eq!(
+            eval_str(
+                r"(\k -> try k (\x -> raise +5) catch \e -> plus e +1) (try callcc \c -> c catch \e -> plus e +100)"
+            ),
+            Ok("6".into())
+        );
+    }
+
+    #[test]
+    fn needs_the_cps_evaluator_for_callcc() {
+        let mut globals = Globals::new();
+        define_callcc(&mut globals);
+        let error = evaluator::eval(
+            &prepare(r"callcc \k -> k"),
+            &globals,
+            BuildOptions::default(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Native error: callcc needs the CPS evaluator"
+        );
+    }
+}
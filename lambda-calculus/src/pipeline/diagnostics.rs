@@ -0,0 +1,169 @@
+use std::fmt::Write;
+
+use crate::parsers::ParserSpan;
+
+use super::errors::{Error, EvalError};
+
+/// Renders an error message followed, when the error knows where it happened,
+/// by an excerpt of `source` (read from `source_name`) with the offending span
+/// underlined. Runtime errors are shown against the source of the expression
+/// that failed, which may have been run long before `source`:
+///
+/// ```text
+/// Type error: Cannot call number 3 as a fn
+///  --> <repl>:1:1
+///   |
+/// 1 | +3 +4
+///   | ^^^^^
+/// ```
+///
+/// An error about several unbound globals is rendered as one such diagnostic
+/// per global.
+pub fn render(error: &Error, source_name: &str, source: &str) -> String {
+    if let Error::Resolve(error) = error {
+        return error
+            .unbound_globals
+            .iter()
+            .map(|unbound| {
+                format!(
+                    "Resolve error: {}\n{}",
+                    unbound,
+                    excerpt(source_name, source, &unbound.span)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+    let mut out = error.to_string();
+    if let Error::Eval(EvalError {
+        span: Some(located),
+        ..
+    }) = error
+    {
+        out.push('\n');
+        out.push_str(&excerpt(
+            &located.source.name,
+            &located.source.text,
+            &located.span,
+        ));
+    } else if let Some(span) = error.span() {
+        out.push('\n');
+        out.push_str(&excerpt(source_name, source, &span));
+    }
+    out
+}
+
+pub fn excerpt(source_name: &str, source: &str, (start, end): &ParserSpan) -> String {
+    let lines: Vec<&str> = source.split('\n').collect();
+    let last_line = end.line.min(lines.len().saturating_sub(1));
+    let gutter_width = (last_line + 1).to_string().len();
+    let mut out = String::new();
+    let start_column = lines
+        .get(start.line)
+        .map_or(start.column, |line| char_column(line, start.column));
+    writeln!(
+        out,
+        "{:gutter_width$}--> {}:{}:{}",
+        "",
+        source_name,
+        start.line + 1,
+        start_column + 1
+    )
+    .unwrap();
+    writeln!(out, "{:gutter_width$} |", "").unwrap();
+    for (line_number, line) in lines.iter().enumerate().take(last_line + 1).skip(start.line) {
+        let from = if line_number == start.line {
+            char_column(line, start.column)
+        } else {
+            0
+        };
+        let to = if line_number == end.line {
+            char_column(line, end.column)
+        } else {
+            line.chars().count()
+        };
+        writeln!(out, "{:>gutter_width$} | {}", line_number + 1, line).unwrap();
+        writeln!(
+            out,
+            "{:gutter_width$} | {}{}",
+            "",
+            " ".repeat(from),
+            "^".repeat(to.saturating_sub(from).max(1))
+        )
+        .unwrap();
+    }
+    out.pop();
+    out
+}
+
+/// Converts a byte offset within `line` into a count of characters, clamping
+/// offsets past the end of the line or in the middle of a character.
+fn char_column(line: &str, byte_column: usize) -> usize {
+    let mut byte_column = byte_column.min(line.len());
+    while !line.is_char_boundary(byte_column) {
+        byte_column -= 1;
+    }
+    line[..byte_column].chars().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::ParserPos;
+    use crate::pipeline::errors::{ResolveError, UnboundGlobal};
+
+    #[test]
+    fn underlines_span_within_a_line() {
+        let span = (ParserPos::new(0, 4), ParserPos::new(0, 9));
+        assert_eq!(
+            excerpt("<repl>", "let x = +3 +4", &span),
+            " --> <repl>:1:5\n  |\n1 | let x = +3 +4\n  |     ^^^^^"
+        );
+    }
+
+    #[test]
+    fn underlines_every_line_of_a_multiline_span() {
+        let span = (ParserPos::new(0, 2), ParserPos::new(1, 3));
+        assert_eq!(
+            excerpt("a.lc", "f (g\n  x)", &span),
+            " --> a.lc:1:3\n  |\n1 | f (g\n  |   ^^\n2 |   x)\n  | ^^^"
+        );
+    }
+
+    #[test]
+    fn counts_columns_in_characters() {
+        let span = (ParserPos::new(0, 3), ParserPos::new(0, 4));
+        assert_eq!(
+            excerpt("<repl>", "λ x", &span),
+            " --> <repl>:1:3\n  |\n1 | λ x\n  |   ^"
+        );
+    }
+
+    #[test]
+    fn locates_every_unbound_global() {
+        let unbound = |name: &str, suggestion: &str, start, end| UnboundGlobal {
+            name: name.to_string(),
+            suggestion: Some(suggestion.to_string()),
+            span: (ParserPos::new(0, start), ParserPos::new(0, end)),
+        };
+        let error = Error::Resolve(ResolveError {
+            unbound_globals: vec![
+                unbound("plsu", "plus", 6, 10),
+                unbound("mlu", "mul", 12, 15),
+            ],
+        });
+        assert_eq!(
+            render(&error, "<repl>", r"\x -> plsu (mlu x)"),
+            "Resolve error: Unbound global `plsu` (did you mean `plus`?)
+ --> <repl>:1:7
+  |
+1 | \\x -> plsu (mlu x)
+  |       ^^^^
+Resolve error: Unbound global `mlu` (did you mean `mul`?)
+ --> <repl>:1:13
+  |
+1 | \\x -> plsu (mlu x)
+  |             ^^^"
+        );
+    }
+}
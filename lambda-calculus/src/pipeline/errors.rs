@@ -0,0 +1,453 @@
+use std::{fmt::Display, io};
+
+use super::runtime::{RTValue, Shared};
+use crate::parsers::Failure;
+pub use crate::parsers::{ParserPos, ParserSpan};
+
+/// Text that spans point into, with the name it is shown under, e.g. `<repl>`
+/// or the path of a file.
+#[derive(Debug)]
+pub struct Source {
+    pub name: String,
+    pub text: String,
+}
+
+impl Source {
+    pub fn new(name: impl Into<String>, text: impl Into<String>) -> Shared<Source> {
+        Shared::new(Source {
+            name: name.into(),
+            text: text.into(),
+        })
+    }
+}
+
+/// A span along with the source it is a span of. Runtime errors carry these,
+/// as a function may fail long after the input that defined it was run.
+#[derive(Debug, Clone)]
+pub struct SourceSpan {
+    pub source: Shared<Source>,
+    pub span: ParserSpan,
+}
+
+impl SourceSpan {
+    pub fn new(source: &Shared<Source>, span: &ParserSpan) -> Self {
+        Self {
+            source: source.clone(),
+            span: span.clone(),
+        }
+    }
+}
+
+/// Any failure along the pipeline, from parsing a statement to running it.
+#[derive(Debug)]
+pub enum Error {
+    Parse(ParseError),
+    Resolve(ResolveError),
+    Module(ModuleError),
+    Eval(EvalError),
+    Test(TestFailure),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: ", self.category())?;
+        self.fmt_message(f)
+    }
+}
+
+impl Error {
+    /// What kind of error this is, which `Display` starts with.
+    fn category(&self) -> &'static str {
+        match self {
+            Error::Parse(_) => "Parse error",
+            Error::Resolve(_) => "Resolve error",
+            Error::Module(_) => "Module error",
+            Error::Test(_) => "Test failure",
+            Error::Eval(error) => match &error.kind {
+                EvalErrorKind::Type(_) => "Type error",
+                EvalErrorKind::Runtime(_) => "Runtime error",
+                EvalErrorKind::Native(_) => "Native error",
+                EvalErrorKind::Raised(_) => "Uncaught error",
+            },
+        }
+    }
+
+    /// Writes what went wrong, without the category.
+    fn fmt_message(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Parse(error) => write!(f, "{}", error),
+            Error::Resolve(error) => write!(f, "{}", error),
+            Error::Module(error) => write!(f, "{}", error),
+            Error::Test(failure) => write!(f, "{}", failure),
+            Error::Eval(error) => match &error.kind {
+                EvalErrorKind::Type(error) => write!(f, "{}", error),
+                EvalErrorKind::Runtime(error) => write!(f, "{}", error),
+                EvalErrorKind::Native(error) => write!(f, "{}", error),
+                EvalErrorKind::Raised(value) => write!(f, "{}", value),
+            },
+        }
+    }
+
+    /// Where in the source the error happened, if known.
+    pub fn span(&self) -> Option<ParserSpan> {
+        match self {
+            Error::Parse(error) => Some(error.span.clone()),
+            Error::Resolve(error) => error
+                .unbound_globals
+                .first()
+                .map(|unbound| unbound.span.clone()),
+            Error::Module(error) => Some(error.span.clone()),
+            Error::Eval(error) => error.span.as_ref().map(|located| located.span.clone()),
+            Error::Test(failure) => Some(failure.span.clone()),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Parse(error) => Some(error),
+            Error::Resolve(error) => Some(error),
+            Error::Module(error) => Some(error),
+            Error::Eval(error) => Some(error),
+            Error::Test(failure) => Some(failure),
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(error: ParseError) -> Self {
+        Error::Parse(error)
+    }
+}
+
+impl From<ResolveError> for Error {
+    fn from(error: ResolveError) -> Self {
+        Error::Resolve(error)
+    }
+}
+
+impl From<ModuleError> for Error {
+    fn from(error: ModuleError) -> Self {
+        Error::Module(error)
+    }
+}
+
+impl From<EvalError> for Error {
+    fn from(error: EvalError) -> Self {
+        Error::Eval(error)
+    }
+}
+
+impl From<TestFailure> for Error {
+    fn from(failure: TestFailure) -> Self {
+        Error::Test(failure)
+    }
+}
+
+/// The statement could not be parsed: at `span`, none of the `expected`
+/// alternatives matched what was `found` (`None` meaning the input ended).
+#[derive(Debug)]
+pub struct ParseError {
+    pub span: ParserSpan,
+    pub expected: Vec<String>,
+    pub found: Option<String>,
+}
+
+impl From<Failure> for ParseError {
+    fn from(Failure { span, expected, found }: Failure) -> Self {
+        Self {
+            span,
+            expected,
+            found,
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Expected ")?;
+        for (i, expected) in self.expected.iter().enumerate() {
+            if i > 0 {
+                f.write_str(if i + 1 == self.expected.len() { " or " } else { ", " })?;
+            }
+            f.write_str(expected)?;
+        }
+        match &self.found {
+            Some(found) => write!(f, ", but found `{}`", found),
+            None => f.write_str(", but reached end of input"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Free variables that do not refer to any known global.
+#[derive(Debug)]
+pub struct ResolveError {
+    pub unbound_globals: Vec<UnboundGlobal>,
+}
+
+#[derive(Debug)]
+pub struct UnboundGlobal {
+    pub name: String,
+    pub suggestion: Option<String>,
+    pub span: ParserSpan,
+}
+
+impl Display for ResolveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, unbound) in self.unbound_globals.iter().enumerate() {
+            if i > 0 {
+                f.write_str("\n")?;
+            }
+            write!(f, "{}", unbound)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for UnboundGlobal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unbound global `{}`", self.name)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, " (did you mean `{}`?)", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+/// An `import`, `export` or qualified name that cannot be made sense of,
+/// located at the statement or name in question.
+#[derive(Debug)]
+pub struct ModuleError {
+    pub kind: ModuleErrorKind,
+    pub span: ParserSpan,
+}
+
+#[derive(Debug)]
+pub enum ModuleErrorKind {
+    Read {
+        path: String,
+        error: io::Error,
+    },
+    /// Files that import each other, starting and ending with the same one.
+    Cycle(Vec<String>),
+    /// Running the imported file failed.
+    InFile {
+        path: String,
+        error: Box<Error>,
+    },
+    UnknownModule(String),
+    NotExported {
+        module: String,
+        name: String,
+    },
+    NotDefined(String),
+    ExportOutsideModule,
+}
+
+impl Display for ModuleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            ModuleErrorKind::Read { path, error } => {
+                write!(f, "Could not read {}: {}", path, error)
+            }
+            ModuleErrorKind::Cycle(paths) => write!(f, "Import cycle: {}", paths.join(" -> ")),
+            ModuleErrorKind::InFile { path, error } => {
+                write!(f, "In {}: ", path)?;
+                error.fmt_message(f)
+            }
+            ModuleErrorKind::UnknownModule(name) => write!(f, "No module imported as `{}`", name),
+            ModuleErrorKind::NotExported { module, name } => {
+                write!(f, "Module `{}` does not export `{}`", module, name)
+            }
+            ModuleErrorKind::NotDefined(name) => {
+                write!(
+                    f,
+                    "Cannot export `{}`, which the module does not define",
+                    name
+                )
+            }
+            ModuleErrorKind::ExportOutsideModule => {
+                f.write_str("Only modules can export definitions")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ModuleError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ModuleErrorKind::Read { error, .. } => Some(error),
+            ModuleErrorKind::InFile { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// A `test` whose expression evaluated to something other than expected, both
+/// shown as values.
+#[derive(Debug)]
+pub struct TestFailure {
+    pub name: String,
+    pub actual: String,
+    pub expected: String,
+    pub span: ParserSpan,
+}
+
+impl Display for TestFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "`{}` evaluated to {}, but expected {}",
+            self.name, self.actual, self.expected
+        )
+    }
+}
+
+impl std::error::Error for TestFailure {}
+
+/// Failures while running a compiled expression, located at the innermost
+/// expression known to have caused it.
+#[derive(Debug)]
+pub struct EvalError {
+    pub kind: EvalErrorKind,
+    pub span: Option<SourceSpan>,
+}
+
+#[derive(Debug)]
+pub enum EvalErrorKind {
+    Type(TypeError),
+    Runtime(RuntimeError),
+    Native(NativeError),
+    /// A value passed to `raise`, to be given to the nearest `catch`.
+    Raised(RTValue),
+}
+
+impl EvalError {
+    /// Locates the error at `span`, unless it was already located somewhere
+    /// more specific.
+    pub fn at(mut self, span: &SourceSpan) -> Self {
+        if self.span.is_none() {
+            self.span = Some(span.clone());
+        }
+        self
+    }
+
+    /// What a `catch` handler receives: the raised value, or the message of
+    /// any other error as a string.
+    pub fn into_value(self) -> RTValue {
+        match self.kind {
+            EvalErrorKind::Raised(value) => value,
+            _ => RTValue::String(self.to_string().into()),
+        }
+    }
+}
+
+impl Display for EvalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            EvalErrorKind::Type(error) => error.fmt(f),
+            EvalErrorKind::Runtime(error) => error.fmt(f),
+            EvalErrorKind::Native(error) => error.fmt(f),
+            EvalErrorKind::Raised(value) => value.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            EvalErrorKind::Type(error) => Some(error),
+            EvalErrorKind::Runtime(error) => Some(error),
+            EvalErrorKind::Native(error) => Some(error),
+            EvalErrorKind::Raised(_) => None,
+        }
+    }
+}
+
+impl From<EvalErrorKind> for EvalError {
+    fn from(kind: EvalErrorKind) -> Self {
+        EvalError { kind, span: None }
+    }
+}
+
+impl From<TypeError> for EvalError {
+    fn from(error: TypeError) -> Self {
+        EvalErrorKind::Type(error).into()
+    }
+}
+
+impl From<RuntimeError> for EvalError {
+    fn from(error: RuntimeError) -> Self {
+        EvalErrorKind::Runtime(error).into()
+    }
+}
+
+impl From<NativeError> for EvalError {
+    fn from(error: NativeError) -> Self {
+        EvalErrorKind::Native(error).into()
+    }
+}
+
+/// A value of the wrong kind was used, e.g. a number was called as a function.
+#[derive(Debug)]
+pub enum TypeError {
+    NotAFunction { found: String },
+    Mismatch { expected: String, found: String },
+}
+
+impl Display for TypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TypeError::NotAFunction { found } => write!(f, "Cannot call {} as a fn", found),
+            TypeError::Mismatch { expected, found } => {
+                write!(f, "Expected {}, got {}", expected, found)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+#[derive(Debug)]
+pub enum RuntimeError {
+    UnboundGlobal(String),
+    UnboundLocal(usize),
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeError::UnboundGlobal(name) => write!(f, "Unbound global {}", name),
+            RuntimeError::UnboundLocal(index) => write!(f, "Unbound local index {}", index),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// A failure reported by a function implemented in Rust.
+#[derive(Debug)]
+pub struct NativeError {
+    pub message: String,
+}
+
+impl NativeError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for NativeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for NativeError {}
@@ -1,90 +1,409 @@
-use std::rc::Rc;
-
 use super::{
-    locally_nameless_tree::{Apply, BoundVar, Expr, FreeVar, Lambda, Lookup},
-    runtime::{Globals, Locals, RTValue},
+    errors::{Error, EvalError, ResolveError, RuntimeError, SourceSpan, TypeError, UnboundGlobal},
+    locally_nameless_tree::{Apply, BoundVar, Expr, FreeVar, Lambda, Lookup, Sequence, TryCatch},
+    modules::Namespace,
+    runtime::{Globals, Locals, RTFunction, RTValue, Shared, ThreadSafe},
 };
 
-struct BuildError {
-    msg: String,
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BuildOptions<'a> {
+    /// Name of the global being defined, which its own definition may refer to.
+    pub defining: Option<&'a str>,
+    /// Skip checking free variables against the globals, so that a script may
+    /// refer to definitions that only appear further down.
+    pub allow_forward_references: bool,
+    /// Where the expression was written, so that only names it can refer to
+    /// are suggested for unbound ones. Without it, the globals everyone shares.
+    pub namespace: Option<&'a Namespace>,
 }
 
-pub trait CompiledClosure {
-    fn run(&self, globals: &Globals, locals: Locals) -> Result<RTValue, String>;
+pub trait CompiledClosure: ThreadSafe {
+    fn run(&self, globals: &Globals, locals: Locals) -> Result<RTValue, EvalError>;
 }
 
 impl<T> CompiledClosure for T
 where
-    T: Clone + Fn(&Globals, Locals) -> Result<RTValue, String>,
+    T: Clone + Fn(&Globals, Locals) -> Result<RTValue, EvalError> + ThreadSafe,
 {
-    fn run(&self, globals: &Globals, locals: Locals) -> Result<RTValue, String> {
+    fn run(&self, globals: &Globals, locals: Locals) -> Result<RTValue, EvalError> {
         self(globals, locals)
     }
 }
 
-pub fn eval(expr: &Expr, globals: &Globals) -> Result<RTValue, String> {
-    match go_expr(expr) {
-        Ok(expr) => expr.run(globals, Locals::empty()),
-        Err(build_error) => Err(format!("Build error: {}", build_error.msg)),
-    }
+pub fn eval(expr: &Expr, globals: &Globals, options: BuildOptions) -> Result<RTValue, Error> {
+    let compiled = build(expr, globals, options)?;
+    Ok(compiled.run(globals, Locals::empty())?)
 }
 
-fn go_expr(expr: &Expr) -> Result<Box<dyn CompiledClosure>, BuildError> {
-    Ok(match expr {
-        Expr::Lambda(lambda) => Box::new(go_lambda(lambda)?),
-        Expr::Apply(apply) => Box::new(go_apply(apply)?),
-        Expr::Lookup(lookup) => go_lookup(lookup)?,
-        Expr::LitInteger(i) => {
-            let i = *i;
-            Box::new(move |_: &Globals, _: Locals| Ok(RTValue::Integer(i)))
-        }
-    })
+pub fn build(
+    expr: &Expr,
+    globals: &Globals,
+    options: BuildOptions,
+) -> Result<Box<dyn CompiledClosure>, ResolveError> {
+    let mut builder = Builder {
+        globals,
+        options,
+        unbound_globals: vec![],
+    };
+    let compiled = builder.go_expr(expr);
+    if builder.unbound_globals.is_empty() {
+        Ok(compiled)
+    } else {
+        Err(ResolveError {
+            unbound_globals: builder.unbound_globals,
+        })
+    }
 }
 
-fn go_lambda(lambda: &Lambda) -> Result<impl Clone + CompiledClosure, BuildError> {
-    let run_body: Rc<dyn CompiledClosure> = Rc::from(go_expr(&lambda.body)?);
-    Ok(move |_: &Globals, locals: Locals| {
-        let run_body = run_body.clone();
-        Ok(RTValue::Function(Rc::new(move |arg, globals| {
-            run_body.run(globals, locals.bind_local(arg))
-        })))
-    })
+struct Builder<'a> {
+    globals: &'a Globals,
+    options: BuildOptions<'a>,
+    unbound_globals: Vec<UnboundGlobal>,
 }
 
-fn go_apply(
-    Apply { function, argument }: &Apply,
-) -> Result<impl Clone + CompiledClosure, BuildError> {
-    let function: Rc<dyn CompiledClosure> = Rc::from(go_expr(function)?);
-    let argument: Rc<dyn CompiledClosure> = Rc::from(go_expr(argument)?);
-    Ok(
+impl<'a> Builder<'a> {
+    fn go_expr(&mut self, expr: &Expr) -> Box<dyn CompiledClosure> {
+        match expr {
+            Expr::Lambda(lambda) => Box::new(self.go_lambda(lambda)),
+            Expr::Apply(apply) => Box::new(self.go_apply(apply)),
+            Expr::TryCatch(try_catch) => Box::new(self.go_try_catch(try_catch)),
+            Expr::Sequence(sequence) => Box::new(self.go_sequence(sequence)),
+            Expr::Lookup(lookup) => self.go_lookup(lookup),
+            Expr::LitInteger(i) => {
+                let i = *i;
+                Box::new(move |_: &Globals, _: Locals| Ok(RTValue::Integer(i)))
+            }
+            Expr::LitString(s) => {
+                let s: Shared<str> = Shared::from(&s[..]);
+                Box::new(move |_: &Globals, _: Locals| Ok(RTValue::String(s.clone())))
+            }
+        }
+    }
+
+    fn go_lambda(&mut self, lambda: &Lambda) -> impl Clone + CompiledClosure {
+        let run_body: Shared<dyn CompiledClosure> = Shared::from(self.go_expr(&lambda.body));
+        let body = Shared::new(lambda.body.clone());
+        move |_: &Globals, locals: Locals| {
+            let run_body = run_body.clone();
+            let captured = locals.clone();
+            Ok(RTValue::Function(RTFunction::lambda(
+                body.clone(),
+                captured,
+                move |arg, globals| run_body.run(globals, locals.bind_local(arg)),
+            )))
+        }
+    }
+
+    fn go_apply(
+        &mut self,
+        Apply {
+            function,
+            argument,
+            span,
+        }: &Apply,
+    ) -> impl Clone + CompiledClosure {
+        let function: Shared<dyn CompiledClosure> = Shared::from(self.go_expr(function));
+        let argument: Shared<dyn CompiledClosure> = Shared::from(self.go_expr(argument));
+        let span = span.clone();
         move |globals: &Globals, locals: Locals| match function.run(globals, locals.clone())? {
             RTValue::Function(run_fn) => {
                 let arg = argument.run(globals, locals)?;
-                (*run_fn)(arg, globals)
+                run_fn.call(arg, globals).map_err(|error| error.at(&span))
+            }
+            other => Err(not_a_function(&other).at(&span)),
+        }
+    }
+
+    fn go_try_catch(&mut self, try_catch: &TryCatch) -> impl Clone + CompiledClosure {
+        let body: Shared<dyn CompiledClosure> = Shared::from(self.go_expr(&try_catch.body));
+        let handler: Shared<dyn CompiledClosure> = Shared::from(self.go_expr(&try_catch.handler));
+        let span = try_catch.span.clone();
+        move |globals: &Globals, locals: Locals| match body.run(globals, locals.clone()) {
+            Ok(value) => Ok(value),
+            Err(error) => match handler.run(globals, locals)? {
+                RTValue::Function(handler) => handler
+                    .call(error.into_value(), globals)
+                    .map_err(|error| error.at(&span)),
+                other => Err(not_a_function(&other).at(&span)),
+            },
+        }
+    }
+
+    fn go_sequence(&mut self, sequence: &Sequence) -> impl Clone + CompiledClosure {
+        let first: Shared<dyn CompiledClosure> = Shared::from(self.go_expr(&sequence.first));
+        let second: Shared<dyn CompiledClosure> = Shared::from(self.go_expr(&sequence.second));
+        move |globals: &Globals, locals: Locals| {
+            first.run(globals, locals.clone())?;
+            second.run(globals, locals)
+        }
+    }
+
+    fn go_lookup(&mut self, lookup: &Lookup) -> Box<dyn CompiledClosure> {
+        match lookup {
+            Lookup::Free(FreeVar { name, span }) => {
+                self.check_global(name, span);
+                let name = name.clone();
+                let span = span.clone();
+                Box::new(move |globals: &Globals, _: Locals| {
+                    globals.lookup(&name[..]).ok_or_else(|| {
+                        EvalError::from(RuntimeError::UnboundGlobal(name.clone())).at(&span)
+                    })
+                })
+            }
+
+            Lookup::Bound(BoundVar {
+                de_brujn_index,
+                span,
+            }) => {
+                let de_brujn_index = *de_brujn_index;
+                let span = span.clone();
+                Box::new(move |_: &Globals, locals: Locals| {
+                    locals.lookup(de_brujn_index).ok_or_else(|| {
+                        EvalError::from(RuntimeError::UnboundLocal(de_brujn_index)).at(&span)
+                    })
+                })
             }
-            RTValue::Integer(i) => Err(format!("Cannot call number {} as a fn", i)),
-        },
-    )
+        }
+    }
+
+    fn check_global(&mut self, name: &str, span: &SourceSpan) {
+        if self.options.allow_forward_references
+            || self.options.defining == Some(name)
+            || self.globals.lookup(name).is_some()
+            || self.unbound_globals.iter().any(|unbound| unbound.name == name)
+        {
+            return;
+        }
+        let visible = match self.options.namespace {
+            Some(namespace) => namespace.visible_names(self.globals),
+            None => Namespace::default().visible_names(self.globals),
+        };
+        self.unbound_globals.push(UnboundGlobal {
+            name: name.to_string(),
+            suggestion: suggest(name, visible.iter().map(|name| &name[..])),
+            span: span.span.clone(),
+        });
+    }
+}
+
+/// Picks the known name closest to `name`, if any is close enough to be a
+/// plausible typo.
+fn suggest<'a>(name: &str, known: impl Iterator<Item = &'a str>) -> Option<String> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    known
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate.to_string())
 }
 
-fn go_lookup(lookup: &Lookup) -> Result<Box<dyn CompiledClosure>, BuildError> {
-    match lookup {
-        Lookup::Free(FreeVar { name }) => {
-            let name = name.clone();
-            Ok(Box::new(move |globals: &Globals, _: Locals| {
-                globals
-                    .lookup(&name[..])
-                    .ok_or_else(|| format!("Unbound global {}", name))
-            }))
+pub fn not_a_function(value: &RTValue) -> EvalError {
+    let found = match value {
+        RTValue::Integer(i) => format!("number {}", i),
+        other => other.to_string(),
+    };
+    TypeError::NotAFunction { found }.into()
+}
+
+/// Optimal string alignment distance: the number of single character
+/// insertions, deletions, substitutions or adjacent transpositions needed to
+/// turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitution = distances[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            let deletion = distances[i - 1][j] + 1;
+            let insertion = distances[i][j - 1] + 1;
+            distances[i][j] = substitution.min(deletion).min(insertion);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distances[i][j] = distances[i][j].min(distances[i - 2][j - 2] + 1);
+            }
         }
+    }
+    distances[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::{modules::Module, native};
+    use crate::test_support::prepare;
 
-        Lookup::Bound(BoundVar { de_brujn_index }) => {
-            let de_brujn_index = *de_brujn_index;
-            Ok(Box::new(move |_: &Globals, locals: Locals| {
-                locals
-                    .lookup(de_brujn_index)
-                    .ok_or_else(|| format!("Unbound local index {}", de_brujn_index))
-            }))
+    fn build_str(text: &str, globals: &Globals, options: BuildOptions) -> Result<(), ResolveError> {
+        build(&prepare(text), globals, options).map(|_| ())
+    }
+
+    fn globals_with(names: &[&str]) -> Globals {
+        let mut globals = Globals::new();
+        for name in names {
+            globals.define(name, RTValue::Integer(0));
         }
+        globals
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("plus", "plus"), 0);
+        assert_eq!(edit_distance("plsu", "plus"), 1);
+        assert_eq!(edit_distance("plu", "plus"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn reports_every_unbound_global_once() {
+        let globals = globals_with(&["plus", "repeatedly"]);
+        let error = build_str(
+            r"\x -> plsu x (repeatdly foo) (foo x)",
+            &globals,
+            BuildOptions::default(),
+        )
+        .unwrap_err();
+        let reported: Vec<(&str, Option<&str>)> = error
+            .unbound_globals
+            .iter()
+            .map(|unbound| (&unbound.name[..], unbound.suggestion.as_deref()))
+            .collect();
+        assert_eq!(
+            reported,
+            vec![
+                ("plsu", Some("plus")),
+                ("repeatdly", Some("repeatedly")),
+                ("foo", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn suggests_only_names_that_can_be_written() {
+        let globals = globals_with(&["repeatedly@2", "lists.repeatedly", "lists.length"]);
+        let suggestion = |text: &str, options: BuildOptions| {
+            build_str(text, &globals, options)
+                .unwrap_err()
+                .unbound_globals[0]
+                .suggestion
+                .clone()
+        };
+        assert_eq!(suggestion("repeatedly", BuildOptions::default()), None);
+        let mut namespace = Namespace::default();
+        namespace.import(
+            Shared::new(Module {
+                name: "lists".to_string(),
+                exports: [("length".to_string(), "lists.length".to_string())].into(),
+            }),
+            Some("l"),
+        );
+        let options = BuildOptions {
+            namespace: Some(&namespace),
+            ..BuildOptions::default()
+        };
+        assert_eq!(
+            suggestion("l.lenght", options),
+            Some("l.length".to_string())
+        );
+        assert_eq!(suggestion("l.repeatedly", options), None);
+    }
+
+    #[test]
+    fn allows_self_reference_and_forward_references_when_asked() {
+        let globals = globals_with(&["plus"]);
+        let recursive = BuildOptions {
+            defining: Some("loop"),
+            ..BuildOptions::default()
+        };
+        assert!(build_str(r"\x -> loop x", &globals, recursive).is_ok());
+        let forward = BuildOptions {
+            allow_forward_references: true,
+            ..BuildOptions::default()
+        };
+        assert!(build_str(r"\x -> later x", &globals, forward).is_ok());
+        assert!(build_str(r"\x -> later x", &globals, BuildOptions::default()).is_err());
+    }
+
+    #[test]
+    fn locates_runtime_errors_at_the_offending_application() {
+        let globals = Globals::new();
+        let error = build(
+            &prepare(r"(\f -> f +1) (\x -> +2 x)"),
+            &globals,
+            BuildOptions::default(),
+        )
+        .ok()
+        .unwrap()
+        .run(&globals, Locals::empty())
+        .err()
+        .unwrap();
+        assert_eq!(error.to_string(), "Cannot call number 2 as a fn");
+        let (start, end) = error.span.unwrap().span;
+        assert_eq!((start.column, end.column), (20, 24));
+    }
+
+    #[test]
+    fn catches_raised_values_and_other_errors() {
+        let mut globals = Globals::new();
+        native::define_builtins(&mut globals);
+        let eval_str = |text: &str| {
+            eval(&prepare(text), &globals, BuildOptions::default())
+                .map(|value| value.to_string())
+                .map_err(|error| error.to_string())
+        };
+        assert_eq!(
+            eval_str(r"try raise +1 catch \e -> plus e +1"),
+            Ok("2".into())
+        );
+        assert_eq!(eval_str(r"try div +6 +3 catch \e -> e"), Ok("2".into()));
+        assert_eq!(
+            eval_str(r"try div +1 +0 catch \e -> e"),
+            Ok("\"Division by zero\"".into())
+        );
+        assert_eq!(
+            eval_str(r"(\x -> try x +1 catch \e -> raise e) +2"),
+            Err("Uncaught error: \"Cannot call number 2 as a fn\"".into())
+        );
+        assert_eq!(
+            eval_str(r"try raise +1 catch +2"),
+            Err("Type error: Cannot call number 2 as a fn".into())
+        );
+        let error = eval(
+            &prepare(r"+1; try raise +1 catch +2"),
+            &globals,
+            BuildOptions::default(),
+        )
+        .unwrap_err();
+        let (start, end) = error.span().unwrap();
+        assert_eq!((start.column, end.column), (4, 25));
+    }
+
+    #[test]
+    fn sequences_effects_on_refs() {
+        let mut globals = Globals::new();
+        native::define_builtins(&mut globals);
+        let eval_str = |text: &str| {
+            eval(&prepare(text), &globals, BuildOptions::default())
+                .map(|value| value.to_string())
+                .map_err(|error| error.to_string())
+        };
+        assert_eq!(
+            eval_str(
+                r"(\count -> (\next -> next +0; next +0; next +0) (\u -> set count (plus (get count) +1))) (ref +0)"
+            ),
+            Ok("3".into())
+        );
+        assert_eq!(
+            eval_str(r"(\r -> set r (\x -> get r); r) (ref +0)"),
+            Ok(r"ref \a -> get <cycle>".into())
+        );
+        assert_eq!(
+            eval_str(r"(\r -> (try set r +1; raise +2 catch \e -> e); get r) (ref +0)"),
+            Ok("1".into())
+        );
     }
 }
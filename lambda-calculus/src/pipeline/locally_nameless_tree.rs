@@ -1,9 +1,71 @@
+use std::fmt::Display;
+
+use super::errors::SourceSpan;
+use super::parse_tree::quote;
+
 #[derive(Debug, Clone)]
 pub enum Expr {
     Lambda(Box<Lambda>),
     Apply(Box<Apply>),
     Lookup(Lookup),
     LitInteger(i64),
+    LitString(String),
+    TryCatch(Box<TryCatch>),
+    Sequence(Box<Sequence>),
+}
+
+impl Expr {
+    /// Names of the globals the expression refers to, each once, in the order
+    /// they first appear.
+    pub fn free_names(&self) -> Vec<&str> {
+        fn go<'a>(expr: &'a Expr, names: &mut Vec<&'a str>) {
+            match expr {
+                Expr::Lambda(lambda) => go(&lambda.body, names),
+                Expr::Apply(apply) => {
+                    go(&apply.function, names);
+                    go(&apply.argument, names);
+                }
+                Expr::TryCatch(try_catch) => {
+                    go(&try_catch.body, names);
+                    go(&try_catch.handler, names);
+                }
+                Expr::Sequence(sequence) => {
+                    go(&sequence.first, names);
+                    go(&sequence.second, names);
+                }
+                Expr::Lookup(Lookup::Free(free)) => {
+                    if !names.contains(&&free.name[..]) {
+                        names.push(&free.name);
+                    }
+                }
+                Expr::Lookup(Lookup::Bound(_)) | Expr::LitInteger(_) | Expr::LitString(_) => {}
+            }
+        }
+        let mut names = vec![];
+        go(self, &mut names);
+        names
+    }
+}
+
+/// Shows the tree as an s-expression, with bound variables written as their
+/// de Bruijn index, e.g. `(lambda (apply f #0))`.
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expr::Lambda(lambda) => write!(f, "(lambda {})", lambda.body),
+            Expr::Apply(apply) => write!(f, "(apply {} {})", apply.function, apply.argument),
+            Expr::Lookup(Lookup::Free(free)) => f.write_str(&free.name),
+            Expr::Lookup(Lookup::Bound(bound)) => write!(f, "#{}", bound.de_brujn_index),
+            Expr::LitInteger(i) => write!(f, "{:+}", i),
+            Expr::LitString(s) => f.write_str(&quote(s)),
+            Expr::TryCatch(try_catch) => {
+                write!(f, "(try {} {})", try_catch.body, try_catch.handler)
+            }
+            Expr::Sequence(sequence) => {
+                write!(f, "(seq {} {})", sequence.first, sequence.second)
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -21,11 +83,45 @@ impl Lambda {
 pub struct Apply {
     pub function: Expr,
     pub argument: Expr,
+    pub span: SourceSpan,
 }
 
 impl Apply {
-    pub fn new(function: Expr, argument: Expr) -> Self {
-        Self { function, argument }
+    pub fn new(function: Expr, argument: Expr, span: SourceSpan) -> Self {
+        Self {
+            function,
+            argument,
+            span,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TryCatch {
+    pub body: Expr,
+    pub handler: Expr,
+    pub span: SourceSpan,
+}
+
+impl TryCatch {
+    pub fn new(body: Expr, handler: Expr, span: SourceSpan) -> Self {
+        Self {
+            body,
+            handler,
+            span,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Sequence {
+    pub first: Expr,
+    pub second: Expr,
+}
+
+impl Sequence {
+    pub fn new(first: Expr, second: Expr) -> Self {
+        Self { first, second }
     }
 }
 
@@ -38,21 +134,26 @@ pub enum Lookup {
 #[derive(Debug, Clone)]
 pub struct FreeVar {
     pub name: String,
+    pub span: SourceSpan,
 }
 
 impl FreeVar {
-    pub fn new(name: String) -> Self {
-        Self { name }
+    pub fn new(name: String, span: SourceSpan) -> Self {
+        Self { name, span }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct BoundVar {
     pub de_brujn_index: usize,
+    pub span: SourceSpan,
 }
 
 impl BoundVar {
-    pub fn new(de_brujn_index: usize) -> Self {
-        Self { de_brujn_index }
+    pub fn new(de_brujn_index: usize, span: SourceSpan) -> Self {
+        Self {
+            de_brujn_index,
+            span,
+        }
     }
 }
@@ -0,0 +1,588 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::parsers::ParserSpan;
+
+use super::{
+    errors::{Error, ModuleError, ModuleErrorKind, Source},
+    evaluator::BuildOptions,
+    locally_nameless_tree::{Expr, FreeVar, Lookup},
+    parse_to_locally_nameless,
+    parse_tree::{self, Import, ImportSource, Stmt},
+    parser,
+    runtime::{Globals, RTValue, Shared},
+};
+
+/// Runs an expression against the globals: [`super::evaluator::eval`] or
+/// [`super::cps::eval`].
+pub type Eval = fn(&Expr, &Globals, BuildOptions) -> Result<RTValue, Error>;
+
+/// A loaded file. Its definitions are globals named after the module, e.g.
+/// `math.square`, so that they do not clash with those of other modules.
+#[derive(Debug)]
+pub struct Module {
+    pub name: String,
+    /// The globals importers can see, by the name the module gives them.
+    pub exports: BTreeMap<String, String>,
+}
+
+/// When the globals a definition uses are looked up.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    /// Every time they are used, so that redefining a global changes what the
+    /// definitions made before do.
+    #[default]
+    Late,
+    /// When the definition is made. Redefining a global makes a new version of
+    /// it, and the definitions made before keep using the previous one.
+    Snapshot,
+}
+
+/// The name a global was defined with, without the version that
+/// [`Binding::Snapshot`] adds to redefinitions, e.g. `plus` for `plus@2`.
+pub fn written_name(key: &str) -> &str {
+    key.split_once('@').map_or(key, |(name, _)| name)
+}
+
+/// Which globals the names used in some source refer to: its own definitions,
+/// what it imported, and otherwise the globals everyone shares, such as the
+/// natives and the prelude.
+#[derive(Debug, Clone, Default)]
+pub struct Namespace {
+    /// Empty at the top level, or the module name followed by a `.`.
+    prefix: String,
+    binding: Binding,
+    /// Names defined here, and the globals they are defined as.
+    own: HashMap<String, String>,
+    /// Names imported without qualification, and the globals they refer to.
+    imported: HashMap<String, String>,
+    /// Modules imported with qualification, by qualifier.
+    qualified: HashMap<String, Shared<Module>>,
+}
+
+impl Namespace {
+    /// The namespace of the REPL and of embedding programs, whose definitions
+    /// are globals under their own names.
+    pub fn top_level(binding: Binding) -> Self {
+        Self {
+            binding,
+            ..Self::default()
+        }
+    }
+
+    /// Chooses how definitions made from now on bind the globals they use,
+    /// keeping those already made.
+    pub fn set_binding(&mut self, binding: Binding) {
+        self.binding = binding;
+    }
+
+    /// The namespace of the module `name`, which defines `definitions`. They are
+    /// all known upfront, so that the module can refer to later ones.
+    fn module<'a>(name: &str, definitions: impl Iterator<Item = &'a str>) -> Self {
+        let prefix = format!("{}.", name);
+        Self {
+            own: definitions
+                .map(|name| (name.to_string(), format!("{}{}", prefix, name)))
+                .collect(),
+            prefix,
+            ..Self::default()
+        }
+    }
+
+    /// The global that `name` is defined as here, if it is.
+    fn key(&self, name: &str) -> String {
+        self.own
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| format!("{}{}", self.prefix, name))
+    }
+
+    /// The global that a `let` of `name` defines: the one `name` refers to,
+    /// unless bindings are snapshots and that one is already defined.
+    pub fn key_to_define(&self, name: &str, globals: &Globals) -> String {
+        let key = self.key(name);
+        if self.binding == Binding::Late || globals.lookup(&key).is_none() {
+            return key;
+        }
+        (2..)
+            .map(|version| format!("{}{}@{}", self.prefix, name, version))
+            .find(|key| globals.lookup(key).is_none())
+            .unwrap()
+    }
+
+    /// Notes that `name` was defined as the global `key`, so that it refers to
+    /// that from now on, even if something with the same name was imported.
+    pub fn define(&mut self, name: &str, key: &str) {
+        self.imported.remove(name);
+        self.own.insert(name.to_string(), key.to_string());
+    }
+
+    /// Brings in what `module` exports, qualified with `qualifier` if given.
+    pub fn import(&mut self, module: Shared<Module>, qualifier: Option<&str>) {
+        match qualifier {
+            Some(qualifier) => {
+                self.qualified.insert(qualifier.to_string(), module);
+            }
+            None => {
+                for (name, key) in &module.exports {
+                    self.own.remove(name);
+                    self.imported.insert(name.clone(), key.clone());
+                }
+            }
+        }
+    }
+
+    /// The global `name` refers to, if it can refer to any.
+    pub fn resolve(&self, name: &str) -> Result<String, ModuleErrorKind> {
+        if let Some((qualifier, name)) = name.split_once('.') {
+            let module = self
+                .qualified
+                .get(qualifier)
+                .ok_or_else(|| ModuleErrorKind::UnknownModule(qualifier.to_string()))?;
+            return module
+                .exports
+                .get(name)
+                .cloned()
+                .ok_or_else(|| ModuleErrorKind::NotExported {
+                    module: qualifier.to_string(),
+                    name: name.to_string(),
+                });
+        }
+        Ok(match (self.own.get(name), self.imported.get(name)) {
+            (Some(key), _) | (None, Some(key)) => key.clone(),
+            (None, None) => name.to_string(),
+        })
+    }
+
+    /// The names that refer to something here, sorted: the shared globals and
+    /// those defined here, without versions, what was imported, and what
+    /// modules imported with qualification export, e.g. `m.square`.
+    pub fn visible_names(&self, globals: &Globals) -> Vec<String> {
+        let mut names: Vec<String> = globals
+            .names()
+            .filter(|key| written_name(key) == *key && !key.contains('.'))
+            .map(str::to_string)
+            .chain(self.own.keys().cloned())
+            .chain(self.imported.keys().cloned())
+            .chain(self.qualified.iter().flat_map(|(qualifier, module)| {
+                module
+                    .exports
+                    .keys()
+                    .map(move |name| format!("{}.{}", qualifier, name))
+            }))
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    /// Turns `expr`, parsed from `source`, into a runnable expression whose
+    /// free variables name the globals they refer to.
+    pub fn prepare(
+        &self,
+        expr: &parse_tree::Expr,
+        source: &Shared<Source>,
+    ) -> Result<Expr, ModuleError> {
+        let mut prepared = parse_to_locally_nameless::transform_expr(expr, source);
+        self.qualify(&mut prepared, None)?;
+        Ok(prepared)
+    }
+
+    /// Like [`Namespace::prepare`], for the expression of a `let` of `name`,
+    /// which may refer to itself. Gives the global to define along with it.
+    pub fn prepare_definition(
+        &self,
+        name: &str,
+        expr: &parse_tree::Expr,
+        source: &Shared<Source>,
+        globals: &Globals,
+    ) -> Result<(String, Expr), ModuleError> {
+        let key = self.key_to_define(name, globals);
+        let mut prepared = parse_to_locally_nameless::transform_expr(expr, source);
+        self.qualify(&mut prepared, Some((name, &key)))?;
+        Ok((key, prepared))
+    }
+
+    /// Rewrites free variables into the globals they refer to. Inside lambdas,
+    /// which run once the definition is made, `defining`, a name and the global
+    /// it is being defined as, refers to that global.
+    fn qualify(&self, expr: &mut Expr, defining: Option<(&str, &str)>) -> Result<(), ModuleError> {
+        self.qualify_in(expr, defining, false)
+    }
+
+    fn qualify_in(
+        &self,
+        expr: &mut Expr,
+        defining: Option<(&str, &str)>,
+        in_lambda: bool,
+    ) -> Result<(), ModuleError> {
+        match expr {
+            Expr::Lambda(lambda) => self.qualify_in(&mut lambda.body, defining, true),
+            Expr::Apply(apply) => {
+                self.qualify_in(&mut apply.function, defining, in_lambda)?;
+                self.qualify_in(&mut apply.argument, defining, in_lambda)
+            }
+            Expr::TryCatch(try_catch) => {
+                self.qualify_in(&mut try_catch.body, defining, in_lambda)?;
+                self.qualify_in(&mut try_catch.handler, defining, in_lambda)
+            }
+            Expr::Sequence(sequence) => {
+                self.qualify_in(&mut sequence.first, defining, in_lambda)?;
+                self.qualify_in(&mut sequence.second, defining, in_lambda)
+            }
+            Expr::Lookup(Lookup::Free(FreeVar { name, span })) => {
+                *name = match defining {
+                    Some((defined, key)) if defined == name && in_lambda => key.to_string(),
+                    _ => self.resolve(name).map_err(|kind| ModuleError {
+                        kind,
+                        span: span.span.clone(),
+                    })?,
+                };
+                Ok(())
+            }
+            Expr::Lookup(Lookup::Bound(_)) | Expr::LitInteger(_) | Expr::LitString(_) => Ok(()),
+        }
+    }
+}
+
+/// Loads the files `import` statements refer to, running each one once, however
+/// many times it is imported.
+pub struct Modules {
+    eval: Eval,
+    allow_forward_references: bool,
+    loaded: HashMap<PathBuf, Shared<Module>>,
+    /// Files being loaded, each imported by the one before, to tell cycles.
+    loading: Vec<(PathBuf, String)>,
+    names: HashSet<String>,
+}
+
+impl Modules {
+    pub fn new(eval: Eval, allow_forward_references: bool) -> Self {
+        Self {
+            eval,
+            allow_forward_references,
+            loaded: HashMap::new(),
+            loading: vec![],
+            names: HashSet::new(),
+        }
+    }
+
+    /// Runs `import`, found in source from `directory`, adding what it brings in
+    /// to `namespace`.
+    pub fn import(
+        &mut self,
+        import: &Import,
+        directory: &Path,
+        namespace: &mut Namespace,
+        globals: &mut Globals,
+    ) -> Result<Shared<Module>, Error> {
+        let path = match &import.source {
+            ImportSource::Path(path) => directory.join(path),
+            ImportSource::Name(name) => directory.join(format!("{}.lc", name)),
+        };
+        let module = self.load(&path, &import.span, globals)?;
+        namespace.import(module.clone(), import.qualifier());
+        Ok(module)
+    }
+
+    fn load(
+        &mut self,
+        path: &Path,
+        span: &ParserSpan,
+        globals: &mut Globals,
+    ) -> Result<Shared<Module>, Error> {
+        let shown = path.display().to_string();
+        let error = |kind| {
+            Error::from(ModuleError {
+                kind,
+                span: span.clone(),
+            })
+        };
+        let read_error = |error| ModuleErrorKind::Read {
+            path: shown.clone(),
+            error,
+        };
+        let canonical = fs::canonicalize(path).map_err(|e| error(read_error(e)))?;
+        if let Some(module) = self.loaded.get(&canonical) {
+            return Ok(module.clone());
+        }
+        if let Some(start) = self
+            .loading
+            .iter()
+            .position(|(loading, _)| *loading == canonical)
+        {
+            let mut cycle: Vec<String> = self.loading[start..]
+                .iter()
+                .map(|(_, shown)| shown.clone())
+                .collect();
+            cycle.push(shown);
+            return Err(error(ModuleErrorKind::Cycle(cycle)));
+        }
+        let source = fs::read_to_string(&canonical).map_err(|e| error(read_error(e)))?;
+        self.loading.push((canonical.clone(), shown.clone()));
+        // run from `path` as written, so that the files it imports are shown
+        // the same way
+        let result = self.run(path, Source::new(&shown, source), globals);
+        self.loading.pop();
+        let module = Shared::new(result.map_err(|inner| match inner {
+            // reported where the cycle is entered, with the whole cycle
+            Error::Module(ModuleError {
+                kind: ModuleErrorKind::Cycle(cycle),
+                ..
+            }) => error(ModuleErrorKind::Cycle(cycle)),
+            inner => error(ModuleErrorKind::InFile {
+                path: shown,
+                error: Box::new(inner),
+            }),
+        })?);
+        self.loaded.insert(canonical, module.clone());
+        Ok(module)
+    }
+
+    /// Runs the statements of a module, returning what it exports.
+    fn run(
+        &mut self,
+        path: &Path,
+        source: Shared<Source>,
+        globals: &mut Globals,
+    ) -> Result<Module, Error> {
+        let stmts = parser::parse_program(&source.text)?;
+        let name = self.unique_name(path);
+        let mut namespace = Namespace::module(
+            &name,
+            stmts.iter().filter_map(|stmt| match stmt {
+                Stmt::Decl(decl) => Some(&decl.identifier[..]),
+                _ => None,
+            }),
+        );
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let mut exported: Option<Vec<(String, ParserSpan)>> = None;
+        for stmt in stmts {
+            match stmt {
+                Stmt::Decl(decl) => {
+                    let (key, expr) = namespace.prepare_definition(
+                        &decl.identifier,
+                        &decl.expr,
+                        &source,
+                        globals,
+                    )?;
+                    namespace.define(&decl.identifier, &key);
+                    let options = BuildOptions {
+                        defining: Some(&key),
+                        allow_forward_references: self.allow_forward_references,
+                        namespace: Some(&namespace),
+                    };
+                    let value = (self.eval)(&expr, globals, options)?;
+                    globals.define(&key, value);
+                }
+                Stmt::Expr(expr) => {
+                    let expr = namespace.prepare(&expr, &source)?;
+                    let options = BuildOptions {
+                        defining: None,
+                        allow_forward_references: self.allow_forward_references,
+                        namespace: Some(&namespace),
+                    };
+                    (self.eval)(&expr, globals, options)?;
+                }
+                Stmt::Import(import) => {
+                    self.import(&import, directory, &mut namespace, globals)?;
+                }
+                // run by `lambda-calculus test` on the module itself, not when
+                // it is imported
+                Stmt::Test(_) => {}
+                Stmt::Export(export) => exported.get_or_insert_with(Vec::new).extend(
+                    export
+                        .names
+                        .into_iter()
+                        .map(|name| (name, export.span.clone())),
+                ),
+            }
+        }
+        let exports = match exported {
+            None => namespace.own.into_iter().collect(),
+            Some(names) => names
+                .into_iter()
+                .map(|(name, span)| match namespace.own.get(&name) {
+                    Some(key) => Ok((name, key.clone())),
+                    None => Err(ModuleError {
+                        kind: ModuleErrorKind::NotDefined(name),
+                        span,
+                    }),
+                })
+                .collect::<Result<_, _>>()?,
+        };
+        Ok(Module { name, exports })
+    }
+
+    /// The name of the file without its extension, numbered if another module
+    /// already has that name.
+    fn unique_name(&mut self, path: &Path) -> String {
+        let stem = path.file_stem().map_or_else(
+            || "module".to_string(),
+            |stem| stem.to_string_lossy().into_owned(),
+        );
+        let name = (1..)
+            .map(|i| match i {
+                1 => stem.clone(),
+                i => format!("{}{}", stem, i),
+            })
+            .find(|name| !self.names.contains(name))
+            .unwrap();
+        self.names.insert(name.clone());
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::{evaluator, native};
+    use crate::test_support::TempDir;
+
+    /// A directory of its own for each test, with the given files in it.
+    fn directory_with(test: &str, files: &[(&str, &str)]) -> TempDir {
+        let directory = TempDir::new(&format!("modules-{}", test));
+        for (name, source) in files {
+            directory.write(name, source);
+        }
+        directory
+    }
+
+    struct Session {
+        globals: Globals,
+        namespace: Namespace,
+        modules: Modules,
+        directory: PathBuf,
+    }
+
+    impl Session {
+        fn new(directory: PathBuf) -> Self {
+            let mut globals = Globals::new();
+            native::define_builtins(&mut globals);
+            Self {
+                globals,
+                namespace: Namespace::top_level(Binding::Late),
+                modules: Modules::new(evaluator::eval, false),
+                directory,
+            }
+        }
+
+        fn run(&mut self, text: &str) -> Result<String, String> {
+            let run = |session: &mut Session| -> Result<String, Error> {
+                match parser::parse_stmt(text)? {
+                    Stmt::Import(import) => {
+                        session.modules.import(
+                            &import,
+                            &session.directory,
+                            &mut session.namespace,
+                            &mut session.globals,
+                        )?;
+                        Ok(String::new())
+                    }
+                    Stmt::Expr(expr) => {
+                        let expr = session
+                            .namespace
+                            .prepare(&expr, &Source::new("<test>", text))?;
+                        let value =
+                            evaluator::eval(&expr, &session.globals, BuildOptions::default())?;
+                        Ok(value.to_string())
+                    }
+                    other => panic!("unexpected {:?}", other),
+                }
+            };
+            run(self).map_err(|error| error.to_string())
+        }
+    }
+
+    #[test]
+    fn keeps_modules_in_their_own_namespaces() {
+        let directory = directory_with(
+            "namespaces",
+            &[
+                (
+                    "math.lc",
+                    "export square\nlet times = \\a -> \\b -> repeatedly b (plus a) +0\nlet square = \\x -> times x x\n",
+                ),
+                ("other.lc", "let times = +7\nlet seven = times\n"),
+            ],
+        );
+        let mut session = Session::new(directory.path().to_path_buf());
+        assert_eq!(session.run("import math as m"), Ok("".into()));
+        assert_eq!(session.run("import \"other.lc\""), Ok("".into()));
+        // each module sees its own `times`
+        assert_eq!(session.run("m.square +4"), Ok("16".into()));
+        assert_eq!(session.run("seven"), Ok("7".into()));
+        assert_eq!(session.run("times"), Ok("7".into()));
+        assert_eq!(
+            session.run("m.times"),
+            Err("Module error: Module `m` does not export `times`".into())
+        );
+        assert_eq!(
+            session.run("math.square"),
+            Err("Module error: No module imported as `math`".into())
+        );
+    }
+
+    #[test]
+    fn loads_modules_once_and_imports_relative_to_the_importer() {
+        let directory = directory_with(
+            "once",
+            &[
+                (
+                    "main.lc",
+                    "import \"lib/counter.lc\"\nlet twice = plus count count\n",
+                ),
+                (
+                    "shared.lc",
+                    "import \"lib/counter.lc\" as c\nlet again = c.count\n",
+                ),
+            ],
+        );
+        directory.write(
+            "lib/counter.lc",
+            "import state\nlet count = set state.cell (plus (get state.cell) +1)\n",
+        );
+        directory.write("lib/state.lc", "let cell = ref +0\n");
+        let mut session = Session::new(directory.path().to_path_buf());
+        session.run("import main").unwrap();
+        session.run("import shared").unwrap();
+        assert_eq!(session.run("main.twice"), Ok("2".into()));
+        assert_eq!(session.run("shared.again"), Ok("1".into()));
+    }
+
+    #[test]
+    fn reports_cycles_and_bad_exports() {
+        let directory = directory_with(
+            "cycles",
+            &[
+                ("a.lc", "import b\nlet x = +1\n"),
+                ("b.lc", "import a\n"),
+                ("exports.lc", "let x = +1\nexport x, y\n"),
+            ],
+        );
+        fs::create_dir_all(directory.path().join("mods")).unwrap();
+        let mut session = Session::new(directory.path().to_path_buf());
+        // shown as imported, not as the canonical path of each file
+        assert_eq!(
+            session.run("import \"mods/../a.lc\""),
+            Err(format!(
+                "Module error: Import cycle: {0}/mods/../a.lc -> {0}/mods/../b.lc -> {0}/mods/../a.lc",
+                directory.path().display()
+            ))
+        );
+        assert_eq!(
+            session.run("import exports"),
+            Err(format!(
+                "Module error: In {}/exports.lc: Cannot export `y`, which the module does not define",
+                directory.path().display()
+            ))
+        );
+        assert!(session
+            .run("import missing")
+            .unwrap_err()
+            .starts_with("Module error: Could not read"));
+    }
+}
@@ -0,0 +1,612 @@
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+};
+
+use super::{
+    errors::{EvalError, EvalErrorKind, NativeError, TypeError},
+    runtime::{FunctionOrigin, Globals, RTFunction, RTRef, RTValue, Shared, ThreadSafe},
+};
+
+/// Arguments that native functions can take, converted from runtime values.
+pub trait FromRTValue: Sized {
+    fn from_rt_value(value: RTValue) -> Result<Self, EvalError>;
+}
+
+/// Results that native functions can return, converted to runtime values.
+pub trait IntoRTValue {
+    fn into_rt_value(self) -> Result<RTValue, EvalError>;
+}
+
+impl FromRTValue for RTValue {
+    fn from_rt_value(value: RTValue) -> Result<Self, EvalError> {
+        Ok(value)
+    }
+}
+
+impl FromRTValue for i64 {
+    fn from_rt_value(value: RTValue) -> Result<Self, EvalError> {
+        match value {
+            RTValue::Integer(value) => Ok(value),
+            other => Err(mismatch("number", &other)),
+        }
+    }
+}
+
+impl FromRTValue for RTFunction {
+    fn from_rt_value(value: RTValue) -> Result<Self, EvalError> {
+        match value {
+            RTValue::Function(function) => Ok(function),
+            other => Err(mismatch("function", &other)),
+        }
+    }
+}
+
+impl FromRTValue for String {
+    fn from_rt_value(value: RTValue) -> Result<Self, EvalError> {
+        match value {
+            RTValue::String(s) => Ok(s.to_string()),
+            other => Err(mismatch("string", &other)),
+        }
+    }
+}
+
+impl FromRTValue for RTRef {
+    fn from_rt_value(value: RTValue) -> Result<Self, EvalError> {
+        match value {
+            RTValue::Ref(reference) => Ok(reference),
+            other => Err(mismatch("ref", &other)),
+        }
+    }
+}
+
+fn mismatch(expected: &str, found: &RTValue) -> EvalError {
+    TypeError::Mismatch {
+        expected: expected.to_string(),
+        found: found.to_string(),
+    }
+    .into()
+}
+
+impl IntoRTValue for RTValue {
+    fn into_rt_value(self) -> Result<RTValue, EvalError> {
+        Ok(self)
+    }
+}
+
+impl IntoRTValue for i64 {
+    fn into_rt_value(self) -> Result<RTValue, EvalError> {
+        Ok(RTValue::Integer(self))
+    }
+}
+
+impl IntoRTValue for RTFunction {
+    fn into_rt_value(self) -> Result<RTValue, EvalError> {
+        Ok(RTValue::Function(self))
+    }
+}
+
+impl IntoRTValue for String {
+    fn into_rt_value(self) -> Result<RTValue, EvalError> {
+        Ok(RTValue::String(Shared::from(self)))
+    }
+}
+
+impl IntoRTValue for RTRef {
+    fn into_rt_value(self) -> Result<RTValue, EvalError> {
+        Ok(RTValue::Ref(self))
+    }
+}
+
+impl<T: IntoRTValue> IntoRTValue for Result<T, EvalError> {
+    fn into_rt_value(self) -> Result<RTValue, EvalError> {
+        self?.into_rt_value()
+    }
+}
+
+/// Marks natives that only look at their arguments, e.g. `|a: i64, b: i64| a + b`.
+pub struct Pure;
+
+/// Marks natives that also take the globals as a last argument, which they
+/// need to call functions they were given.
+pub struct WithGlobals;
+
+pub trait Native1<A, M> {
+    fn call(&self, a: A, globals: &Globals) -> Result<RTValue, EvalError>;
+}
+
+impl<F, A, R> Native1<A, Pure> for F
+where
+    F: Fn(A) -> R,
+    R: IntoRTValue,
+{
+    fn call(&self, a: A, _: &Globals) -> Result<RTValue, EvalError> {
+        self(a).into_rt_value()
+    }
+}
+
+impl<F, A, R> Native1<A, WithGlobals> for F
+where
+    F: Fn(A, &Globals) -> R,
+    R: IntoRTValue,
+{
+    fn call(&self, a: A, globals: &Globals) -> Result<RTValue, EvalError> {
+        self(a, globals).into_rt_value()
+    }
+}
+
+pub trait Native2<A, B, M> {
+    fn call(&self, a: A, b: B, globals: &Globals) -> Result<RTValue, EvalError>;
+}
+
+impl<F, A, B, R> Native2<A, B, Pure> for F
+where
+    F: Fn(A, B) -> R,
+    R: IntoRTValue,
+{
+    fn call(&self, a: A, b: B, _: &Globals) -> Result<RTValue, EvalError> {
+        self(a, b).into_rt_value()
+    }
+}
+
+impl<F, A, B, R> Native2<A, B, WithGlobals> for F
+where
+    F: Fn(A, B, &Globals) -> R,
+    R: IntoRTValue,
+{
+    fn call(&self, a: A, b: B, globals: &Globals) -> Result<RTValue, EvalError> {
+        self(a, b, globals).into_rt_value()
+    }
+}
+
+pub trait Native3<A, B, C, M> {
+    fn call(&self, a: A, b: B, c: C, globals: &Globals) -> Result<RTValue, EvalError>;
+}
+
+impl<F, A, B, C, R> Native3<A, B, C, Pure> for F
+where
+    F: Fn(A, B, C) -> R,
+    R: IntoRTValue,
+{
+    fn call(&self, a: A, b: B, c: C, _: &Globals) -> Result<RTValue, EvalError> {
+        self(a, b, c).into_rt_value()
+    }
+}
+
+impl<F, A, B, C, R> Native3<A, B, C, WithGlobals> for F
+where
+    F: Fn(A, B, C, &Globals) -> R,
+    R: IntoRTValue,
+{
+    fn call(&self, a: A, b: B, c: C, globals: &Globals) -> Result<RTValue, EvalError> {
+        self(a, b, c, globals).into_rt_value()
+    }
+}
+
+type Check = fn(&RTValue) -> Result<(), EvalError>;
+
+#[cfg(not(feature = "sync"))]
+type Finish = dyn Fn(Vec<RTValue>, &Globals) -> Result<RTValue, EvalError>;
+#[cfg(feature = "sync")]
+type Finish = dyn Fn(Vec<RTValue>, &Globals) -> Result<RTValue, EvalError> + Send + Sync;
+
+fn check<T: FromRTValue>(value: &RTValue) -> Result<(), EvalError> {
+    T::from_rt_value(value.clone()).map(|_| ())
+}
+
+/// Takes the next argument of a native, making sure it has the expected type
+/// right away, so that a wrong argument is reported where it is given.
+fn curry(
+    name: Shared<str>,
+    checks: Shared<[Check]>,
+    applied: Vec<RTValue>,
+    finish: Shared<Finish>,
+) -> RTFunction {
+    let arity = checks.len();
+    RTFunction::native(
+        &name.clone(),
+        arity,
+        applied.clone(),
+        move |arg, globals| {
+            checks[applied.len()](&arg)?;
+            let mut applied = applied.clone();
+            applied.push(arg);
+            if applied.len() == arity {
+                finish(applied, globals)
+            } else {
+                Ok(RTValue::Function(curry(
+                    name.clone(),
+                    checks.clone(),
+                    applied,
+                    finish.clone(),
+                )))
+            }
+        },
+    )
+}
+
+fn arg<T: FromRTValue>(args: &mut impl Iterator<Item = RTValue>) -> Result<T, EvalError> {
+    T::from_rt_value(
+        args.next()
+            .expect("natives get as many arguments as their arity"),
+    )
+}
+
+/// Registering natives written as plain Rust functions, which get curried and
+/// have their arguments converted, e.g.
+/// `globals.define_native2("plus", |a: i64, b: i64| a + b)`.
+impl Globals {
+    pub fn define_native1<A, M>(
+        &mut self,
+        name: &str,
+        native: impl Native1<A, M> + ThreadSafe + 'static,
+    ) where
+        A: FromRTValue,
+    {
+        let finish = move |args: Vec<RTValue>, globals: &Globals| {
+            let mut args = args.into_iter();
+            native.call(arg(&mut args)?, globals)
+        };
+        self.define_curried(name, Shared::new([check::<A>]), Shared::new(finish));
+    }
+
+    pub fn define_native2<A, B, M>(
+        &mut self,
+        name: &str,
+        native: impl Native2<A, B, M> + ThreadSafe + 'static,
+    ) where
+        A: FromRTValue,
+        B: FromRTValue,
+    {
+        let finish = move |args: Vec<RTValue>, globals: &Globals| {
+            let mut args = args.into_iter();
+            native.call(arg(&mut args)?, arg(&mut args)?, globals)
+        };
+        self.define_curried(
+            name,
+            Shared::new([check::<A>, check::<B>]),
+            Shared::new(finish),
+        );
+    }
+
+    pub fn define_native3<A, B, C, M>(
+        &mut self,
+        name: &str,
+        native: impl Native3<A, B, C, M> + ThreadSafe + 'static,
+    ) where
+        A: FromRTValue,
+        B: FromRTValue,
+        C: FromRTValue,
+    {
+        let finish = move |args: Vec<RTValue>, globals: &Globals| {
+            let mut args = args.into_iter();
+            native.call(arg(&mut args)?, arg(&mut args)?, arg(&mut args)?, globals)
+        };
+        self.define_curried(
+            name,
+            Shared::new([check::<A>, check::<B>, check::<C>]),
+            Shared::new(finish),
+        );
+    }
+
+    fn define_curried(&mut self, name: &str, checks: Shared<[Check]>, finish: Shared<Finish>) {
+        let function = curry(Shared::from(name), checks, vec![], finish);
+        self.define(name, RTValue::Function(function));
+    }
+}
+
+/// The natives every program can use.
+pub fn define_builtins(globals: &mut Globals) {
+    globals.define_native2("plus", |a: i64, b: i64| a + b);
+    globals.define_native2("div", |a: i64, b: i64| -> Result<i64, EvalError> {
+        match a.checked_div(b) {
+            Some(quotient) => Ok(quotient),
+            None if b == 0 => Err(NativeError::new("Division by zero"))?,
+            None => Err(NativeError::new("Division overflows"))?,
+        }
+    });
+    globals.define_native1("raise", |value: RTValue| -> Result<RTValue, EvalError> {
+        Err(EvalErrorKind::Raised(value).into())
+    });
+    globals.define_native1("ref", |value: RTValue| RTRef::new(value));
+    globals.define_native1("get", |reference: RTRef| reference.get());
+    // gives back the new contents, e.g. the new count after an increment
+    globals.define_native2("set", |reference: RTRef, value: RTValue| {
+        reference.set(value.clone());
+        value
+    });
+    globals.define_native3(
+        "repeatedly",
+        |number_of_times: i64, function: RTFunction, seed: RTValue, globals: &Globals| {
+            let mut v = seed;
+            for _ in 0..number_of_times {
+                v = function.call(v, globals)?;
+            }
+            Ok(v)
+        },
+    );
+    // `par f g x` pairs `f x` and `g x`, computed in parallel, so it is only
+    // defined with the `sync` feature
+    #[cfg(feature = "sync")]
+    globals.define_native3(
+        "par",
+        |f: RTFunction, g: RTFunction, x: RTValue, globals: &Globals| {
+            let (a, b) = both(&f, &g, x, globals)?;
+            Ok(pair(a, b))
+        },
+    );
+}
+
+/// Natives that read and write outside the program, which are only defined
+/// when asked for. They run when applied, so the strict evaluation order
+/// sequences them: in `(\_ -> println "b") (println "a")`, `a` comes first.
+/// `print`, `println` and `readline` go through the [`Console`] of the
+/// globals they run with.
+pub fn define_io(globals: &mut Globals) {
+    globals.define_native1("print", |value: RTValue, globals: &Globals| {
+        write_out(value, "", globals)
+    });
+    globals.define_native1("println", |value: RTValue, globals: &Globals| {
+        write_out(value, "\n", globals)
+    });
+    globals.define_native1(
+        "readline",
+        |prompt: String, globals: &Globals| -> Result<String, EvalError> {
+            write_out(RTValue::String(Shared::from(prompt)), "", globals)?;
+            match globals.console().read_line() {
+                Ok(Some(line)) => Ok(line),
+                Ok(None) => Err(NativeError::new("Reached end of input"))?,
+                Err(error) => Err(NativeError::new(format!(
+                    "Could not read a line: {}",
+                    error
+                )))?,
+            }
+        },
+    );
+    globals.define_native1("readfile", |path: String| -> Result<String, EvalError> {
+        Ok(fs::read_to_string(&path)
+            .map_err(|error| NativeError::new(format!("Could not read {}: {}", path, error)))?)
+    });
+}
+
+/// Removes the natives of [`define_io`], leaving alone any that were redefined
+/// since.
+pub fn undefine_io(globals: &mut Globals) {
+    for io_name in ["print", "println", "readline", "readfile"] {
+        if let Some(RTValue::Function(function)) = globals.lookup(io_name) {
+            if let FunctionOrigin::Native { name, .. } = &function.origin {
+                if &**name == io_name {
+                    globals.undefine(io_name);
+                }
+            }
+        }
+    }
+}
+
+/// Writes strings as their contents and other values as they are shown, and
+/// gives the value back.
+fn write_out(value: RTValue, end: &str, globals: &Globals) -> Result<RTValue, EvalError> {
+    let text = match &value {
+        RTValue::String(s) => format!("{}{}", s, end),
+        other => format!("{}{}", other, end),
+    };
+    globals
+        .console()
+        .write(&text)
+        .map_err(|error| NativeError::new(format!("Could not write: {}", error)))?;
+    Ok(value)
+}
+
+/// Where `print` and `println` write and `readline` reads, so that programs
+/// can be run somewhere other than a terminal.
+pub trait Console: ThreadSafe {
+    fn write(&self, text: &str) -> io::Result<()>;
+
+    /// The next line, without its line ending, or `None` at the end of input.
+    fn read_line(&self) -> io::Result<Option<String>>;
+}
+
+impl<C: Console + ?Sized> Console for Shared<C> {
+    fn write(&self, text: &str) -> io::Result<()> {
+        (**self).write(text)
+    }
+
+    fn read_line(&self) -> io::Result<Option<String>> {
+        (**self).read_line()
+    }
+}
+
+/// The console of the process: standard output and standard input.
+pub struct Stdio;
+
+impl Console for Stdio {
+    fn write(&self, text: &str) -> io::Result<()> {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(text.as_bytes())?;
+        stdout.flush()
+    }
+
+    fn read_line(&self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(line.trim_end_matches(['\n', '\r']).to_string()))
+    }
+}
+
+/// Applies `f` and `g` to `x` in parallel, on rayon's thread pool, which has
+/// as many threads as there are cores however deeply `par` nests. If both
+/// fail, the error from `f` is the one reported, so results do not depend on
+/// which side finishes first.
+#[cfg(feature = "sync")]
+fn both(
+    f: &RTFunction,
+    g: &RTFunction,
+    x: RTValue,
+    globals: &Globals,
+) -> Result<(RTValue, RTValue), EvalError> {
+    let left_x = x.clone();
+    let (left, right) = rayon::join(move || f.call(left_x, globals), move || g.call(x, globals));
+    Ok((left?, right?))
+}
+
+/// The same value as the prelude's `pair a b`, i.e. `\c -> c a b`.
+#[cfg(feature = "sync")]
+fn pair(a: RTValue, b: RTValue) -> RTValue {
+    let applied = vec![a.clone(), b.clone()];
+    RTValue::Function(RTFunction::native("pair", 3, applied, move |c, globals| {
+        let c = RTFunction::from_rt_value(c)?;
+        let partial = RTFunction::from_rt_value(c.call(a.clone(), globals)?)?;
+        partial.call(b.clone(), globals)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(globals: &Globals, name: &str, args: Vec<RTValue>) -> Result<RTValue, EvalError> {
+        let mut value = globals.lookup(name).unwrap();
+        for arg in args {
+            value = RTFunction::from_rt_value(value)?.call(arg, globals)?;
+        }
+        Ok(value)
+    }
+
+    #[test]
+    fn curries_and_converts_arguments() {
+        let mut globals = Globals::new();
+        globals.define_native2("minus", |a: i64, b: i64| a - b);
+        let result = call(
+            &globals,
+            "minus",
+            vec![RTValue::Integer(5), RTValue::Integer(3)],
+        );
+        assert!(matches!(result, Ok(RTValue::Integer(2))));
+
+        let partial = call(&globals, "minus", vec![RTValue::Integer(5)]).unwrap();
+        assert_eq!(partial.to_string(), "<native minus/2> +5");
+    }
+
+    #[test]
+    fn reports_wrong_arguments_as_soon_as_they_are_given() {
+        let mut globals = Globals::new();
+        globals.define_native3("pick", |_: i64, _: RTFunction, c: RTValue| c);
+        let error = call(
+            &globals,
+            "pick",
+            vec![RTValue::Integer(1), RTValue::Integer(2)],
+        )
+        .err()
+        .unwrap();
+        assert_eq!(error.to_string(), "Expected function, got 2");
+    }
+
+    #[test]
+    fn lets_natives_call_functions_they_are_given() {
+        let mut globals = Globals::new();
+        globals.define_native1("double", |a: i64| a * 2);
+        globals.define_native2("twice", |f: RTFunction, x: RTValue, globals: &Globals| {
+            f.call(f.call(x, globals)?, globals)
+        });
+        let double = globals.lookup("double").unwrap();
+        let result = call(&globals, "twice", vec![double, RTValue::Integer(3)]);
+        assert!(matches!(result, Ok(RTValue::Integer(12))));
+    }
+
+    #[test]
+    fn passes_errors_from_natives_along() {
+        let mut globals = Globals::new();
+        globals.define_native1("fail", |_: RTValue| -> Result<i64, EvalError> {
+            Err(crate::pipeline::errors::NativeError::new("no luck"))?
+        });
+        let error = call(&globals, "fail", vec![RTValue::Integer(0)])
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "no luck");
+    }
+
+    #[test]
+    fn shares_ref_cells_between_copies() {
+        let mut globals = Globals::new();
+        define_builtins(&mut globals);
+        let reference = call(&globals, "ref", vec![RTValue::Integer(1)]).unwrap();
+        let copy = reference.clone();
+        let set = call(&globals, "set", vec![copy, RTValue::Integer(2)]);
+        assert!(matches!(set, Ok(RTValue::Integer(2))));
+        let got = call(&globals, "get", vec![reference.clone()]);
+        assert!(matches!(got, Ok(RTValue::Integer(2))));
+        assert_eq!(reference.to_string(), "ref +2");
+
+        call(&globals, "set", vec![reference.clone(), reference.clone()]).unwrap();
+        assert_eq!(reference.to_string(), "ref <cycle>");
+        let error = call(&globals, "get", vec![RTValue::Integer(1)])
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "Expected ref, got 1");
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn runs_both_sides_of_par_and_pairs_the_results() {
+        let mut globals = Globals::new();
+        define_builtins(&mut globals);
+        globals.define_native1("double", |a: i64| a * 2);
+        globals.define_native1("square", |a: i64| a * a);
+        globals.define_native2("first", |a: RTValue, _: RTValue| a);
+        globals.define_native2("second", |_: RTValue, b: RTValue| b);
+        let args = || {
+            vec![
+                globals.lookup("double").unwrap(),
+                globals.lookup("square").unwrap(),
+                RTValue::Integer(5),
+            ]
+        };
+        let result = call(&globals, "par", args()).unwrap();
+        assert_eq!(result.to_string(), "<native pair/3> +10 +25");
+
+        let mut pick_args = args();
+        pick_args.push(globals.lookup("first").unwrap());
+        assert!(matches!(
+            call(&globals, "par", pick_args),
+            Ok(RTValue::Integer(10))
+        ));
+        let mut pick_args = args();
+        pick_args.push(globals.lookup("second").unwrap());
+        assert!(matches!(
+            call(&globals, "par", pick_args),
+            Ok(RTValue::Integer(25))
+        ));
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn reports_the_first_failing_side_of_par() {
+        let mut globals = Globals::new();
+        define_builtins(&mut globals);
+        globals.define_native1("left", |_: RTValue| -> Result<i64, EvalError> {
+            Err(crate::pipeline::errors::NativeError::new("left failed"))?
+        });
+        globals.define_native1("right", |_: RTValue| -> Result<i64, EvalError> {
+            Err(crate::pipeline::errors::NativeError::new("right failed"))?
+        });
+        let args = vec![
+            globals.lookup("left").unwrap(),
+            globals.lookup("right").unwrap(),
+            RTValue::Integer(0),
+        ];
+        for _ in 0..10 {
+            let error = call(&globals, "par", args.clone()).err().unwrap();
+            assert_eq!(error.to_string(), "left failed");
+        }
+    }
+
+    #[cfg(not(feature = "sync"))]
+    #[test]
+    fn leaves_par_undefined_without_threads() {
+        let mut globals = Globals::new();
+        define_builtins(&mut globals);
+        assert!(globals.lookup("par").is_none());
+    }
+}
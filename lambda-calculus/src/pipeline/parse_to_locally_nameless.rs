@@ -1,48 +1,89 @@
+use super::errors::{Source, SourceSpan};
 use super::parse_tree;
 use super::locally_nameless_tree;
+use super::runtime::Shared;
 
-pub fn transform_expr(parsed: &parse_tree::Expr) -> locally_nameless_tree::Expr {
+/// Turns `parsed`, parsed from `source`, into a tree with de Bruijn indices.
+pub fn transform_expr(parsed: &parse_tree::Expr, source: &Shared<Source>) -> locally_nameless_tree::Expr {
     let mut bound_vars: Vec<&str> = vec![];
-    go_expr(parsed, &mut bound_vars)
+    go_expr(parsed, &mut bound_vars, source)
 }
 
-fn go_expr<'a>(parsed: &'a parse_tree::Expr, bound_vars: &mut Vec<&'a str>) -> locally_nameless_tree::Expr {
+fn go_expr<'a>(parsed: &'a parse_tree::Expr, bound_vars: &mut Vec<&'a str>, source: &Shared<Source>) -> locally_nameless_tree::Expr {
     match parsed {
-        parse_tree::Expr::Apply(apply) => go_apply(apply, bound_vars),
-        parse_tree::Expr::Lambda(lambda) => go_lambda(lambda, bound_vars),
-        parse_tree::Expr::Lookup(identifier) => go_lookup(identifier, bound_vars),
+        parse_tree::Expr::Apply(apply) => go_apply(apply, bound_vars, source),
+        parse_tree::Expr::Lambda(lambda) => go_lambda(lambda, bound_vars, source),
+        parse_tree::Expr::Lookup(lookup) => go_lookup(lookup, bound_vars, source),
         parse_tree::Expr::LitInteger(lit) => go_literal(lit),
+        parse_tree::Expr::LitString(lit) => {
+            locally_nameless_tree::Expr::LitString(lit.value.clone())
+        }
+        parse_tree::Expr::TryCatch(try_catch) => go_try_catch(try_catch, bound_vars, source),
+        parse_tree::Expr::Sequence(sequence) => go_sequence(sequence, bound_vars, source),
     }
 }
 
 fn go_apply<'a>(
     parsed: &'a parse_tree::Apply,
     bound_vars: &mut Vec<&'a str>,
+    source: &Shared<Source>,
 ) -> locally_nameless_tree::Expr {
-    let function = go_expr(&parsed.function, bound_vars);
-    let argument = go_expr(&parsed.argument, bound_vars);
-    locally_nameless_tree::Expr::Apply(Box::new(locally_nameless_tree::Apply::new(function, argument)))
+    let function = go_expr(&parsed.function, bound_vars, source);
+    let argument = go_expr(&parsed.argument, bound_vars, source);
+    locally_nameless_tree::Expr::Apply(Box::new(locally_nameless_tree::Apply::new(
+        function,
+        argument,
+        SourceSpan::new(source, &parsed.span),
+    )))
+}
+
+fn go_try_catch<'a>(
+    parsed: &'a parse_tree::TryCatch,
+    bound_vars: &mut Vec<&'a str>,
+    source: &Shared<Source>,
+) -> locally_nameless_tree::Expr {
+    let body = go_expr(&parsed.body, bound_vars, source);
+    let handler = go_expr(&parsed.handler, bound_vars, source);
+    locally_nameless_tree::Expr::TryCatch(Box::new(locally_nameless_tree::TryCatch::new(
+        body,
+        handler,
+        SourceSpan::new(source, &parsed.span),
+    )))
+}
+
+fn go_sequence<'a>(
+    parsed: &'a parse_tree::Sequence,
+    bound_vars: &mut Vec<&'a str>,
+    source: &Shared<Source>,
+) -> locally_nameless_tree::Expr {
+    let first = go_expr(&parsed.first, bound_vars, source);
+    let second = go_expr(&parsed.second, bound_vars, source);
+    locally_nameless_tree::Expr::Sequence(Box::new(locally_nameless_tree::Sequence::new(
+        first, second,
+    )))
 }
 
 fn go_lambda<'a>(
     parsed: &'a parse_tree::Lambda,
     bound_vars: &mut Vec<&'a str>,
+    source: &Shared<Source>,
 ) -> locally_nameless_tree::Expr {
     bound_vars.push(&parsed.param[..]);
-    let body = go_expr(&parsed.body, bound_vars);
+    let body = go_expr(&parsed.body, bound_vars, source);
     bound_vars.pop();
     locally_nameless_tree::Expr::Lambda(Box::new(locally_nameless_tree::Lambda::new(body)))
 }
 
-fn go_lookup(identifier: &String, bound_vars: &[&str]) -> locally_nameless_tree::Expr {
+fn go_lookup(lookup: &parse_tree::Lookup, bound_vars: &[&str], source: &Shared<Source>) -> locally_nameless_tree::Expr {
+    let span = SourceSpan::new(source, &lookup.span);
     locally_nameless_tree::Expr::Lookup(
-        match bound_vars.iter().rev().position(|s| s == identifier) {
-            None => locally_nameless_tree::Lookup::Free(locally_nameless_tree::FreeVar::new(identifier.clone())),
-            Some(i) => locally_nameless_tree::Lookup::Bound(locally_nameless_tree::BoundVar::new(i)),
+        match bound_vars.iter().rev().position(|s| *s == lookup.name) {
+            None => locally_nameless_tree::Lookup::Free(locally_nameless_tree::FreeVar::new(lookup.name.clone(), span)),
+            Some(i) => locally_nameless_tree::Lookup::Bound(locally_nameless_tree::BoundVar::new(i, span)),
         },
     )
 }
 
-fn go_literal(literal: &i64) -> locally_nameless_tree::Expr {
-    locally_nameless_tree::Expr::LitInteger(*literal)
+fn go_literal(literal: &parse_tree::LitInteger) -> locally_nameless_tree::Expr {
+    locally_nameless_tree::Expr::LitInteger(literal.value)
 }
@@ -1,33 +1,188 @@
 #![allow(dead_code)]
 
-#[derive(Debug)]
+use std::fmt::Display;
+
+use crate::parsers::ParserSpan;
+
+#[derive(Debug, PartialEq)]
 pub enum Stmt {
     Expr(Expr),
     Decl(Decl),
+    Import(Import),
+    Export(Export),
+    Test(Test),
+}
+
+impl Stmt {
+    pub fn span(&self) -> &ParserSpan {
+        match self {
+            Stmt::Expr(expr) => expr.span(),
+            Stmt::Decl(decl) => &decl.span,
+            Stmt::Import(import) => &import.span,
+            Stmt::Export(export) => &export.span,
+            Stmt::Test(test) => &test.span,
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Decl {
     pub identifier: String,
     pub expr: Expr,
+    pub span: ParserSpan,
+}
+
+/// `import "lib.lc"` brings what the file exports in as it is named there,
+/// while `import math` or `import "lib.lc" as m` make it available qualified,
+/// as `math.square` or `m.square`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Import {
+    pub source: ImportSource,
+    pub alias: Option<String>,
+    pub span: ParserSpan,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ImportSource {
+    /// A file, relative to the directory of the importing one.
+    Path(String),
+    /// A module name, standing for `name.lc` next to the importing file.
+    Name(String),
+}
+
+impl Import {
+    /// The name to qualify imported names with, if any.
+    pub fn qualifier(&self) -> Option<&str> {
+        match (&self.alias, &self.source) {
+            (Some(alias), _) => Some(alias),
+            (None, ImportSource::Name(name)) => Some(name),
+            (None, ImportSource::Path(_)) => None,
+        }
+    }
+}
+
+/// `export square, cube`: the only definitions of a module its importers can
+/// see. Without it, they can see all of them.
+#[derive(Debug, PartialEq)]
+pub struct Export {
+    pub names: Vec<String>,
+    pub span: ParserSpan,
+}
+
+/// `test "doubles" = double +2 == +4`: checks that `expr` evaluates to the
+/// same value as `expected`.
+#[derive(Debug, PartialEq)]
+pub struct Test {
+    pub name: String,
+    pub expr: Expr,
+    pub expected: Expr,
+    pub span: ParserSpan,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum Expr {
     Lambda(Box<Lambda>),
     Apply(Box<Apply>),
-    Lookup(String),
-    LitInteger(i64),
+    Lookup(Lookup),
+    LitInteger(LitInteger),
+    LitString(LitString),
+    TryCatch(Box<TryCatch>),
+    Sequence(Box<Sequence>),
+}
+
+impl Expr {
+    pub fn span(&self) -> &ParserSpan {
+        match self {
+            Expr::Lambda(lambda) => &lambda.span,
+            Expr::Apply(apply) => &apply.span,
+            Expr::Lookup(lookup) => &lookup.span,
+            Expr::LitInteger(lit) => &lit.span,
+            Expr::LitString(lit) => &lit.span,
+            Expr::TryCatch(try_catch) => &try_catch.span,
+            Expr::Sequence(sequence) => &sequence.span,
+        }
+    }
+}
+
+/// Shows the tree as an s-expression, e.g. `(lambda x (apply f x))`.
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expr::Lambda(lambda) => write!(f, "(lambda {} {})", lambda.param, lambda.body),
+            Expr::Apply(apply) => write!(f, "(apply {} {})", apply.function, apply.argument),
+            Expr::Lookup(lookup) => f.write_str(&lookup.name),
+            Expr::LitInteger(lit) => write!(f, "{:+}", lit.value),
+            Expr::LitString(lit) => f.write_str(&quote(&lit.value)),
+            Expr::TryCatch(try_catch) => {
+                write!(f, "(try {} {})", try_catch.body, try_catch.handler)
+            }
+            Expr::Sequence(sequence) => {
+                write!(f, "(seq {} {})", sequence.first, sequence.second)
+            }
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Lambda {
     pub param: String,
     pub body: Expr,
+    pub span: ParserSpan,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TryCatch {
+    pub body: Expr,
+    pub handler: Expr,
+    pub span: ParserSpan,
+}
+
+/// `first; second`: evaluates `first` for its effects, then `second`.
+#[derive(Debug, PartialEq)]
+pub struct Sequence {
+    pub first: Expr,
+    pub second: Expr,
+    pub span: ParserSpan,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Apply {
     pub function: Expr,
     pub argument: Expr,
+    pub span: ParserSpan,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Lookup {
+    pub name: String,
+    pub span: ParserSpan,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LitInteger {
+    pub value: i64,
+    pub span: ParserSpan,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LitString {
+    pub value: String,
+    pub span: ParserSpan,
+}
+
+/// Writes `value` as a string literal, escaping what the parser expects to be
+/// escaped.
+pub fn quote(value: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
 }
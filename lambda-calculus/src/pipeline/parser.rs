@@ -1,26 +1,72 @@
-use crate::parsers::{delim, pure, string, Parser};
-use crate::pipeline::parse_tree::{Apply, Decl, Expr, Lambda, Stmt};
+use crate::parsers::{delim, one_char, pure, string, Failure, Parser, ParserPos, ParserState};
+use crate::pipeline::errors::ParseError;
+use crate::pipeline::parse_tree::{
+    Apply, Decl, Export, Expr, Import, ImportSource, Lambda, LitInteger, LitString, Lookup,
+    Sequence, Stmt, Test, TryCatch,
+};
 use std::rc::Rc;
 
-pub fn parse_stmt(text: &str) -> Result<Stmt, String> {
-    stmt().parse_str(text)
+pub fn parse_stmt(text: &str) -> Result<Stmt, ParseError> {
+    Ok(stmt().parse_str(text)?)
 }
 
-fn stmt() -> impl Parser<Item = Stmt, ParseError = String> {
+pub fn parse_expr(text: &str) -> Result<Expr, ParseError> {
+    Ok(delim::whitespace()
+        .skip_many::<Failure>()
+        .then(expr())
+        .also(delim::EXPECT_END)
+        .parse_str(text)?)
+}
+
+/// Parses a whole source file. Each statement starts at the beginning of a
+/// line, and any lines after it that are blank or indented continue it.
+pub fn parse_program(text: &str) -> Result<Vec<Stmt>, ParseError> {
+    split_statements(text)
+        .into_iter()
+        .map(|(start_line, chunk)| {
+            Ok(stmt().parse(&mut ParserState::starting_at(chunk, ParserPos::new(start_line, 0)))?)
+        })
+        .collect()
+}
+
+fn split_statements(text: &str) -> Vec<(usize, &str)> {
+    let mut chunks = vec![];
+    let mut chunk_start = (0, 0);
+    let mut offset = 0;
+    for (line_number, line) in text.split_inclusive('\n').enumerate() {
+        let starts_statement = line.chars().next().is_some_and(|c| !c.is_whitespace());
+        if starts_statement && offset > chunk_start.1 {
+            chunks.push((chunk_start.0, &text[chunk_start.1..offset]));
+            chunk_start = (line_number, offset);
+        } else if starts_statement {
+            chunk_start = (line_number, offset);
+        }
+        offset += line.len();
+    }
+    chunks.push((chunk_start.0, &text[chunk_start.1..]));
+    chunks.retain(|(_, chunk)| !chunk.trim().is_empty());
+    chunks
+}
+
+fn stmt() -> impl Parser<Item = Stmt, ParseError = Failure> {
     delim::whitespace()
-        .skip_many::<String>()
+        .skip_many::<Failure>()
         .then(
             (decl())
                 .map(Stmt::Decl)
+                .falling_back(import().map(Stmt::Import))
+                .falling_back(export().map(Stmt::Export))
+                .falling_back(test().map(Stmt::Test))
                 .falling_back(expr().map(Stmt::Expr)),
         )
         .also(delim::EXPECT_END)
 }
 
-fn decl() -> impl Parser<Item = Decl, ParseError = String> {
-    string::expect("let")
-        .then(delim::whitespace().skip_at_least_one())
-        .then(identifier())
+fn decl() -> impl Parser<Item = Decl, ParseError = Failure> {
+    position()
+        .also(string::expect("let"))
+        .also(delim::whitespace().skip_at_least_one())
+        .paired_with(identifier())
         .also(
             delim::whitespace()
                 .skip_at_least_one()
@@ -29,95 +75,284 @@ fn decl() -> impl Parser<Item = Decl, ParseError = String> {
         )
         .paired_with(expr())
         .also(delim::whitespace().skip_many())
-        .map(|(identifier, expr)| Decl { identifier, expr })
+        .map(|((start, identifier), expr)| {
+            let span = (start, expr.span().1.clone());
+            Decl {
+                identifier,
+                expr,
+                span,
+            }
+        })
 }
 
-fn expr() -> impl Parser<Item = Expr, ParseError = String> {
+fn test() -> impl Parser<Item = Test, ParseError = Failure> {
+    position()
+        .also(keyword("test"))
+        .also(delim::whitespace().skip_at_least_one())
+        .paired_with(literal_string())
+        .also(
+            delim::whitespace()
+                .skip_at_least_one()
+                .then(string::expect("="))
+                .then(delim::whitespace().skip_at_least_one()),
+        )
+        .paired_with(expr())
+        .also(delim::whitespace().skip_many())
+        .also(string::expect("=="))
+        .also(delim::whitespace().skip_many())
+        .paired_with(expr())
+        .also(delim::whitespace().skip_many())
+        .map(|(((start, name), expr), expected)| {
+            let span = (start, expected.span().1.clone());
+            Test {
+                name,
+                expr,
+                expected,
+                span,
+            }
+        })
+}
+
+fn import() -> impl Parser<Item = Import, ParseError = Failure> {
+    let source = literal_string()
+        .map(ImportSource::Path)
+        .falling_back(identifier().map(ImportSource::Name));
+    let alias = keyword("as")
+        .also(delim::whitespace().skip_at_least_one())
+        .then(identifier());
+    position()
+        .also(keyword("import"))
+        .also(delim::whitespace().skip_at_least_one())
+        .paired_with(source)
+        .paired_with(position())
+        .also(delim::whitespace().skip_many())
+        .paired_with(alias.with_span().optional())
+        .also(delim::whitespace().skip_many())
+        .map(|(((start, source), source_end), alias)| {
+            let (alias, end) = match alias {
+                Some((alias, (_, end))) => (Some(alias), end),
+                None => (None, source_end),
+            };
+            Import {
+                source,
+                alias,
+                span: (start, end),
+            }
+        })
+}
+
+fn export() -> impl Parser<Item = Export, ParseError = Failure> {
+    let name = Rc::new(
+        identifier()
+            .with_span()
+            .also(delim::whitespace().skip_many()),
+    );
+    position()
+        .also(keyword("export"))
+        .also(delim::whitespace().skip_at_least_one())
+        .paired_with(name.clone())
+        .paired_with(
+            string::expect(",")
+                .also(delim::whitespace().skip_many())
+                .then(name)
+                .committed_many(),
+        )
+        .map(|((start, first), rest)| {
+            let end = rest.last().unwrap_or(&first).1 .1.clone();
+            let names = std::iter::once(first)
+                .chain(rest)
+                .map(|(name, _)| name)
+                .collect();
+            Export {
+                names,
+                span: (start, end),
+            }
+        })
+}
+
+/// Words that cannot be used as identifiers.
+pub const KEYWORDS: [&str; 6] = ["try", "catch", "import", "export", "as", "test"];
+
+/// An expression, maybe followed by `; expr` to sequence them. Sequences
+/// extend as far as possible, so `a; b; c` is `a; (b; c)`.
+pub fn expr() -> impl Parser<Item = Expr, ParseError = Failure> {
     lambda()
+        .falling_back(try_catch())
         .falling_back(application())
         .also(delim::whitespace().skip_many())
+        .paired_with(
+            string::expect(";")
+                .also(delim::whitespace().skip_many())
+                .then(expr_rec())
+                .optional(),
+        )
+        .map(|(first, second)| match second {
+            None => first,
+            Some(second) => {
+                let span = (first.span().0.clone(), second.span().1.clone());
+                Expr::Sequence(Box::new(Sequence {
+                    first,
+                    second,
+                    span,
+                }))
+            }
+        })
 }
 
-fn expr_rec() -> impl Parser<Item = Expr, ParseError = String> {
+fn expr_rec() -> impl Parser<Item = Expr, ParseError = Failure> {
     pure::run(|state| expr().parse(state))
 }
 
-fn lambda() -> impl Parser<Item = Expr, ParseError = String> {
-    string::expect("\\")
+fn position() -> impl Parser<Item = ParserPos, ParseError = Failure> {
+    pure::run(|state: &mut ParserState| Ok(state.current_position().clone()))
+}
+
+fn lambda() -> impl Parser<Item = Expr, ParseError = Failure> {
+    position()
+        .also(string::expect("\\"))
         .also(delim::whitespace().skip_many())
-        .then(identifier())
+        .paired_with(identifier())
         .also(delim::whitespace().skip_many())
         .also(string::expect("->"))
         .also(delim::whitespace().skip_many())
         .paired_with(expr_rec())
-        .map(|(param, body)| Expr::Lambda(Box::new(Lambda { param, body })))
+        .map(|((start, param), body)| {
+            let span = (start, body.span().1.clone());
+            Expr::Lambda(Box::new(Lambda { param, body, span }))
+        })
+}
+
+/// `try body catch handler`: runs `body`, and if it fails, applies `handler`
+/// to the value it raised. Like a lambda body, the handler extends as far as
+/// possible.
+fn try_catch() -> impl Parser<Item = Expr, ParseError = Failure> {
+    position()
+        .also(keyword("try"))
+        .also(delim::whitespace().skip_at_least_one())
+        .paired_with(expr_rec())
+        .also(keyword("catch"))
+        .also(delim::whitespace().skip_many())
+        .paired_with(expr_rec())
+        .map(|((start, body), handler)| {
+            let span = (start, handler.span().1.clone());
+            Expr::TryCatch(Box::new(TryCatch {
+                body,
+                handler,
+                span,
+            }))
+        })
 }
 
-fn application() -> impl Parser<Item = Expr, ParseError = String> {
-    Rc::new(
+fn application() -> impl Parser<Item = Expr, ParseError = Failure> {
+    let argument = Rc::new(
         parens(expr_rec())
             .falling_back(
-                identifier()
-                    .also(delim::whitespace().skip_many())
-                    .map(Expr::Lookup),
+                qualified_name()
+                    .with_span()
+                    .map(|(name, span)| Expr::Lookup(Lookup { name, span })),
             )
-            .falling_back(literal_integer().map(Expr::LitInteger)),
-    )
-    .at_least_one()
-    // at the end of an application series, we may have a trailing lambda
-    // e.g. f +1 +2 +3 \k -> +4
-    .paired_with(lambda().optional())
-    .map(|((head, mut args), final_lambda)| {
-        if let Some(lambda_expr) = final_lambda {
-            args.push(lambda_expr);
-        }
-        args.into_iter().fold(head, |head, arg| {
-            Expr::Apply(Box::new(Apply {
-                function: head,
-                argument: arg,
-            }))
+            .falling_back(
+                literal_integer()
+                    .with_span()
+                    .map(|(value, span)| Expr::LitInteger(LitInteger { value, span })),
+            )
+            .falling_back(
+                literal_string()
+                    .with_span()
+                    .map(|(value, span)| Expr::LitString(LitString { value, span })),
+            )
+            // spanning the whole argument, so that parentheses are included
+            .with_span()
+            .also(delim::whitespace().skip_many()),
+    );
+    argument
+        .clone()
+        // an argument that breaks halfway, e.g. an unclosed parenthesis, is an
+        // error in itself rather than the end of the application
+        .paired_with(argument.committed_many())
+        // at the end of an application series, we may have a trailing lambda
+        // e.g. f +1 +2 +3 \k -> +4
+        .paired_with(lambda().optional())
+        .map(|(((head, (start, _)), mut args), final_lambda)| {
+            if let Some(lambda_expr) = final_lambda {
+                let span = lambda_expr.span().clone();
+                args.push((lambda_expr, span));
+            }
+            args.into_iter().fold(head, |head, (arg, (_, end))| {
+                Expr::Apply(Box::new(Apply {
+                    function: head,
+                    argument: arg,
+                    span: (start.clone(), end),
+                }))
+            })
         })
-    })
 }
 
-fn parens<P>(parse_item: P) -> impl Parser<Item = P::Item, ParseError = String>
+fn parens<P>(parse_item: P) -> impl Parser<Item = P::Item, ParseError = Failure>
 where
-    P: Parser<ParseError = String>,
+    P: Parser<ParseError = Failure>,
 {
     string::expect("(")
         .also(delim::whitespace().skip_many())
         .then(parse_item)
         .also(delim::whitespace().skip_many())
         .also(string::expect(")"))
-        .also(delim::whitespace().skip_many())
 }
 
-fn identifier() -> impl Parser<Item = String, ParseError = String> {
-    string::many_chars_matching(|c| char::is_ascii_lowercase(&c)).validate(|identifier| {
-        if identifier.is_empty() {
-            Some("Expected identifier (sequence of lowercase ascii letters)".to_string())
-        } else {
-            None
+fn identifier() -> impl Parser<Item = String, ParseError = Failure> {
+    pure::run(|state: &mut ParserState| {
+        let before = state.clone();
+        let name = word().parse(state)?;
+        if KEYWORDS.contains(&&name[..]) {
+            *state = before;
+            return Err(Failure::at(state, "identifier", name.chars().count()));
         }
+        Ok(name)
     })
 }
 
-fn literal_integer() -> impl Parser<Item = i64, ParseError = String> {
+/// An identifier, maybe qualified with the name of an imported module, e.g.
+/// `square` or `m.square`.
+fn qualified_name() -> impl Parser<Item = String, ParseError = Failure> {
+    identifier()
+        .paired_with(string::expect(".").then(identifier()).optional())
+        .map(|(first, second)| match second {
+            Some(second) => format!("{}.{}", first, second),
+            None => first,
+        })
+}
+
+/// Exactly the word `expected`, not just the start of a longer one.
+fn keyword(expected: &'static str) -> impl Parser<Item = (), ParseError = Failure> {
+    pure::run(move |state: &mut ParserState| {
+        let before = state.clone();
+        match word().parse(state) {
+            Ok(found) if found == expected => Ok(()),
+            found => {
+                *state = before;
+                let length = found.map_or(1, |found| found.chars().count());
+                Err(Failure::at(state, format!("`{}`", expected), length))
+            }
+        }
+    })
+}
+
+fn word() -> impl Parser<Item = String, ParseError = Failure> {
+    one_char::matches(|c: char| c.is_ascii_lowercase(), "identifier")
+        .paired_with(string::many_chars_matching(|c: char| c.is_ascii_lowercase()))
+        .map(|(first, rest)| format!("{}{}", first, rest))
+}
+
+fn literal_integer() -> impl Parser<Item = i64, ParseError = Failure> {
     string::expect("-")
         .map(|_| false)
         .falling_back(string::expect("+").map(|_| true))
         .paired_with(
-            string::many_chars_matching(|c: char| c.is_ascii_digit()).validate(|digits| {
-                if digits.is_empty() {
-                    Some("Expected sequence of digits".to_string())
-                } else {
-                    None
-                }
-            }),
+            one_char::matches(|c: char| c.is_ascii_digit(), "digit")
+                .paired_with(string::many_chars_matching(|c: char| c.is_ascii_digit())),
         )
-        .also(delim::whitespace().skip_many())
-        .map(|(is_positive, digits)| {
-            let abs_val = digits.parse::<i64>().unwrap_or(0);
+        .map(|(is_positive, (first, rest))| {
+            let abs_val = format!("{}{}", first, rest).parse::<i64>().unwrap_or(0);
             if is_positive {
                 abs_val
             } else {
@@ -125,3 +360,158 @@ fn literal_integer() -> impl Parser<Item = i64, ParseError = String> {
             }
         })
 }
+
+/// A string between double quotes, in which `\"`, `\\`, `\n` and `\t` stand
+/// for a quote, a backslash, a newline and a tab.
+fn literal_string() -> impl Parser<Item = String, ParseError = Failure> {
+    let plain = one_char::matches(|c: char| c != '"' && c != '\\', "string character");
+    let escaped = string::expect("\\").then(
+        one_char::matches(
+            |c: char| matches!(c, '"' | '\\' | 'n' | 't'),
+            "escape sequence",
+        )
+        .map(|c| match c {
+            'n' => '\n',
+            't' => '\t',
+            c => c,
+        }),
+    );
+    string::expect("\"")
+        .then(plain.falling_back(escaped).committed_many())
+        .also(string::expect("\""))
+        .map(|chars| chars.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(text: &str) -> ParseError {
+        parse_stmt(text).err().unwrap()
+    }
+
+    #[test]
+    fn lists_every_expected_alternative() {
+        let error = parse_error("plus +1 )");
+        assert_eq!(error.expected, vec!["end of input"]);
+        assert_eq!(error.found.as_deref(), Some(")"));
+        assert_eq!((error.span.0.column, error.span.1.column), (8, 9));
+
+        let error = parse_error("\\x -> ");
+        assert_eq!(
+            error.expected,
+            vec!["`\\`", "`try`", "`(`", "identifier", "`-`", "`+`", "`\"`"]
+        );
+        assert_eq!(error.found, None);
+    }
+
+    #[test]
+    fn reports_what_was_found_instead_of_a_token() {
+        let error = parse_error("\\x => x");
+        assert_eq!(error.to_string(), "Expected `->`, but found `=>`");
+    }
+
+    #[test]
+    fn reports_unfinished_arguments_where_they_break() {
+        let error = parse_error("f (g x");
+        assert_eq!(error.expected, vec!["`)`"]);
+        assert_eq!(error.found, None);
+        let error = parse_error("f (g x] y");
+        assert_eq!(error.to_string(), "Expected `)`, but found `]`");
+    }
+
+    #[test]
+    fn handles_multi_byte_characters() {
+        let error = parse_error("\\x é> x");
+        assert_eq!(error.found.as_deref(), Some("é>"));
+        let error = parse_error("f x ééééééééééé");
+        assert_eq!(error.found.as_deref(), Some("éééééééééé"));
+    }
+
+    #[test]
+    fn reads_try_catch_and_keeps_its_keywords_reserved() {
+        match parse_stmt(r"try f tryagain catch \e -> g e").unwrap() {
+            Stmt::Expr(expr) => assert_eq!(
+                expr.to_string(),
+                "(try (apply f tryagain) (lambda e (apply g e)))"
+            ),
+            other => panic!("unexpected {:?}", other),
+        }
+        let error = parse_error("try f x");
+        assert_eq!(error.expected, vec!["`catch`"]);
+        assert_eq!(error.found, None);
+        let error = parse_error(r"\catch -> x");
+        assert_eq!(error.to_string(), "Expected identifier, but found `catch`");
+    }
+
+    #[test]
+    fn reads_sequences_to_the_right() {
+        match parse_stmt(r"let go = f x; \y -> g; h").unwrap() {
+            Stmt::Decl(decl) => assert_eq!(
+                decl.expr.to_string(),
+                "(seq (apply f x) (lambda y (seq g h)))"
+            ),
+            other => panic!("unexpected {:?}", other),
+        }
+        let error = parse_error("f x;");
+        assert_eq!(error.found, None);
+    }
+
+    #[test]
+    fn reads_string_literals_with_escapes() {
+        match parse_stmt(r#"println "say \"hi\"\n\\ é""#).unwrap() {
+            Stmt::Expr(Expr::Apply(apply)) => match &apply.argument {
+                Expr::LitString(lit) => assert_eq!(lit.value, "say \"hi\"\n\\ é"),
+                other => panic!("unexpected {:?}", other),
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+        let error = parse_error(r#"f "abc"#);
+        assert_eq!(error.to_string(), "Expected `\"`, but reached end of input");
+        let error = parse_error(r#"f "a\q""#);
+        assert_eq!(error.to_string(), "Expected escape sequence, but found `q`");
+    }
+
+    #[test]
+    fn reads_imports_exports_and_qualified_names() {
+        match parse_stmt(r#"import "lib/math.lc" as m"#).unwrap() {
+            Stmt::Import(import) => {
+                assert_eq!(import.source, ImportSource::Path("lib/math.lc".into()));
+                assert_eq!(import.qualifier(), Some("m"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse_stmt("import math").unwrap() {
+            Stmt::Import(import) => assert_eq!(import.qualifier(), Some("math")),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse_stmt("export square , cube").unwrap() {
+            Stmt::Export(export) => assert_eq!(export.names, vec!["square", "cube"]),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse_stmt(r"\x -> m.square (math.cube x)").unwrap() {
+            Stmt::Expr(expr) => assert_eq!(
+                expr.to_string(),
+                "(lambda x (apply m.square (apply math.cube x)))"
+            ),
+            other => panic!("unexpected {:?}", other),
+        }
+        let error = parse_error("let as = +1");
+        assert_eq!(error.to_string(), "Expected identifier, but found `as`");
+    }
+    #[test]
+    fn reads_tests() {
+        match parse_stmt(r#"test "doubles" = double +2 == plus +2 +2"#).unwrap() {
+            Stmt::Test(test) => {
+                assert_eq!(test.name, "doubles");
+                assert_eq!(test.expr.to_string(), "(apply double +2)");
+                assert_eq!(test.expected.to_string(), "(apply (apply plus +2) +2)");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(parse_stmt(r#"test "doubles" = double +2"#).is_err());
+        assert!(parse_stmt("test doubles = double +2 == +4").is_err());
+        let error = parse_error("let test = +1");
+        assert_eq!(error.to_string(), "Expected identifier, but found `test`");
+    }
+}
@@ -0,0 +1,152 @@
+use super::{
+    errors::{Error, Source},
+    evaluator::{self, BuildOptions},
+    parse_to_locally_nameless, parse_tree, parser,
+    runtime::Globals,
+};
+
+/// Definitions written in the language itself: combinators, Church booleans,
+/// numerals and pairs, fixed point combinators and Church-encoded lists.
+/// It does not depend on any native function.
+pub const SOURCE: &str = include_str!("prelude.lc");
+
+/// Defines everything in the prelude.
+pub fn load(globals: &mut Globals) -> Result<(), Error> {
+    let source = Source::new("<prelude>", SOURCE);
+    for stmt in parser::parse_program(SOURCE)? {
+        if let parse_tree::Stmt::Decl(decl) = stmt {
+            let value = evaluator::eval(
+                &parse_to_locally_nameless::transform_expr(&decl.expr, &source),
+                globals,
+                BuildOptions {
+                    defining: Some(&decl.identifier),
+                    ..BuildOptions::default()
+                },
+            )?;
+            globals.define(&decl.identifier, value);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::{
+        church::{self, Decoded},
+        printer,
+        runtime::RTValue,
+    };
+    use crate::test_support::eval_in;
+
+    fn eval_in_prelude(text: &str) -> (RTValue, Globals) {
+        let mut globals = Globals::new();
+        load(&mut globals).unwrap();
+        (eval_in(&globals, text), globals)
+    }
+
+    fn decode(text: &str) -> Decoded {
+        let (value, globals) = eval_in_prelude(text);
+        church::decode(&value, &globals).unwrap()
+    }
+
+    fn number(text: &str) -> u64 {
+        match decode(text) {
+            Decoded::Numeral(n) => n,
+            Decoded::ZeroOrFalse => 0,
+            other => panic!("{} is {}", text, other),
+        }
+    }
+
+    fn boolean(text: &str) -> bool {
+        match decode(text) {
+            Decoded::Boolean(b) => b,
+            Decoded::ZeroOrFalse => false,
+            other => panic!("{} is {}", text, other),
+        }
+    }
+
+    #[test]
+    fn is_formatted() {
+        let stmts = parser::parse_program(SOURCE).unwrap();
+        assert_eq!(
+            printer::print_program(&stmts, printer::DEFAULT_WIDTH),
+            SOURCE
+        );
+    }
+
+    #[test]
+    fn combinators() {
+        assert_eq!(number("id two"), 2);
+        assert_eq!(number("const one two"), 1);
+        assert_eq!(number("flip const one two"), 2);
+        assert_eq!(number("compose succ (add two) one"), 4);
+    }
+
+    #[test]
+    fn booleans() {
+        assert!(boolean("true"));
+        assert!(!boolean("false"));
+        assert_eq!(number("if true one two"), 1);
+        assert_eq!(number("if false one two"), 2);
+        assert!(!boolean("not true"));
+        assert!(boolean("not false"));
+        assert!(boolean("and true true"));
+        assert!(!boolean("and true false"));
+        assert!(boolean("or false true"));
+        assert!(!boolean("or false false"));
+    }
+
+    #[test]
+    fn numerals() {
+        assert_eq!(number("zero"), 0);
+        assert_eq!(number("three"), 3);
+        assert_eq!(number("succ three"), 4);
+        assert_eq!(number("add two three"), 5);
+        assert_eq!(number("mul two three"), 6);
+        assert_eq!(number("pow two three"), 8);
+        assert_eq!(number("pred three"), 2);
+        assert_eq!(number("pred zero"), 0);
+        assert_eq!(number("sub three one"), 2);
+        assert_eq!(number("sub one three"), 0);
+        assert!(boolean("iszero zero"));
+        assert!(!boolean("iszero one"));
+        assert!(boolean("leq two three"));
+        assert!(!boolean("leq three two"));
+        assert!(boolean("eq (add one two) three"));
+        assert!(!boolean("eq one two"));
+    }
+
+    #[test]
+    fn pairs() {
+        assert_eq!(number("fst (pair one two)"), 1);
+        assert_eq!(number("snd (pair one two)"), 2);
+    }
+
+    #[test]
+    fn fixed_points() {
+        // a strict language can only run the Z combinator, Y loops forever
+        let factorial = r"z (\fact -> \n -> iszero n (\u -> one) (\u -> mul n (fact (pred n))) id)";
+        assert_eq!(number(&format!("{} three", factorial)), 6);
+        let (y, _) = eval_in_prelude("y");
+        assert_eq!(y.to_string(), r"\a -> (\b -> a (b b)) \b -> a (b b)");
+    }
+
+    #[test]
+    fn lists() {
+        let list = "(cons one (cons two (cons three nil)))";
+        assert_eq!(number(&format!("length {}", list)), 3);
+        assert_eq!(number("length nil"), 0);
+        assert!(boolean("isnil nil"));
+        assert!(!boolean(&format!("isnil {}", list)));
+        assert_eq!(number(&format!("head {}", list)), 1);
+        assert_eq!(number(&format!("head (tail {})", list)), 2);
+        assert_eq!(number(&format!("length (tail {})", list)), 2);
+        assert_eq!(number(&format!("foldr add zero {}", list)), 6);
+        assert_eq!(number(&format!("foldr add zero (map succ {})", list)), 9);
+        assert_eq!(number(&format!("head (filter (leq two) {})", list)), 2);
+        assert_eq!(number(&format!("length (filter (leq two) {})", list)), 2);
+        assert_eq!(number(&format!("length (append {} {})", list, list)), 6);
+        assert_eq!(number(&format!("head (append nil {})", list)), 1);
+    }
+}
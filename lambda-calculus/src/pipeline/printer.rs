@@ -0,0 +1,442 @@
+use super::parse_tree::{
+    quote, Apply, Decl, Export, Expr, Import, ImportSource, Lambda, Sequence, Stmt, Test, TryCatch,
+};
+
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// Prints statements back as source, one after the other, keeping a blank
+/// line wherever the original source had at least one.
+pub fn print_program(stmts: &[Stmt], width: usize) -> String {
+    let mut out = String::new();
+    let mut previous_end_line = None;
+    for stmt in stmts {
+        let (start, end) = stmt.span();
+        if let Some(previous_end_line) = previous_end_line {
+            if start.line > previous_end_line + 1 {
+                out.push('\n');
+            }
+        }
+        out.push_str(&print_stmt(stmt, width));
+        out.push('\n');
+        previous_end_line = Some(end.line);
+    }
+    out
+}
+
+pub fn print_stmt(stmt: &Stmt, width: usize) -> String {
+    match stmt {
+        Stmt::Expr(expr) => Printer { width }.expr(expr, 0, 0),
+        Stmt::Decl(decl) => print_decl(decl, width),
+        Stmt::Import(import) => print_import(import),
+        Stmt::Export(Export { names, .. }) => format!("export {}", names.join(", ")),
+        Stmt::Test(test) => print_test(test, width),
+    }
+}
+
+fn print_test(test: &Test, width: usize) -> String {
+    let printer = Printer { width };
+    let header = format!("test {} =", quote(&test.name));
+    let flat = format!(
+        "{} {} == {}",
+        header,
+        flat_expr(&test.expr),
+        flat_expr(&test.expected)
+    );
+    if printer.fits(0, &flat) {
+        flat
+    } else {
+        format!(
+            "{}\n  {}\n  == {}",
+            header,
+            printer.expr(&test.expr, 2, 2),
+            printer.expr(&test.expected, 5, 2)
+        )
+    }
+}
+
+fn print_import(import: &Import) -> String {
+    let source = match &import.source {
+        ImportSource::Path(path) => quote(path),
+        ImportSource::Name(name) => name.clone(),
+    };
+    match &import.alias {
+        Some(alias) => format!("import {} as {}", source, alias),
+        None => format!("import {}", source),
+    }
+}
+
+pub fn print_decl(decl: &Decl, width: usize) -> String {
+    let printer = Printer { width };
+    let Decl {
+        identifier, expr, ..
+    } = decl;
+    let header = format!("let {} =", identifier);
+    let flat = format!("{} {}", header, flat_expr(expr));
+    if printer.fits(0, &flat) {
+        flat
+    } else if let Expr::Lambda(_) = expr {
+        // lambdas break nicely on their own, keeping the header line short
+        format!("{} {}", header, printer.expr(expr, header.len() + 1, 0))
+    } else {
+        format!("{}\n  {}", header, printer.expr(expr, 2, 2))
+    }
+}
+
+struct Printer {
+    width: usize,
+}
+
+impl Printer {
+    fn fits(&self, column: usize, text: &str) -> bool {
+        !text.contains('\n') && column + text.chars().count() <= self.width
+    }
+
+    /// Lays out `expr` starting at `column`, indenting any line breaks inside it
+    /// by `indent` spaces.
+    fn expr(&self, expr: &Expr, column: usize, indent: usize) -> String {
+        let flat = flat_expr(expr);
+        if self.fits(column, &flat) {
+            return flat;
+        }
+        match expr {
+            Expr::Lambda(lambda) => self.lambda(lambda, indent),
+            Expr::Apply(apply) => self.apply(apply, column, indent),
+            Expr::TryCatch(try_catch) => self.try_catch(try_catch, column, indent),
+            Expr::Sequence(sequence) => self.sequence(sequence, column, indent),
+            Expr::Lookup(_) | Expr::LitInteger(_) | Expr::LitString(_) => flat,
+        }
+    }
+
+    fn lambda(&self, lambda: &Lambda, indent: usize) -> String {
+        // keep a chain of parameters on the first line: \f -> \x ->
+        let mut header = String::new();
+        let mut body = lambda;
+        loop {
+            header.push_str(&format!("\\{} ->", body.param));
+            match &body.body {
+                Expr::Lambda(inner) => {
+                    header.push(' ');
+                    body = inner;
+                }
+                _ => break,
+            }
+        }
+        let body_indent = indent + 2;
+        format!(
+            "{}\n{}{}",
+            header,
+            " ".repeat(body_indent),
+            self.expr(&body.body, body_indent, body_indent)
+        )
+    }
+
+    /// `catch` goes on its own line, indented so that it still continues the
+    /// statement.
+    fn try_catch(&self, try_catch: &TryCatch, column: usize, indent: usize) -> String {
+        let catch_indent = indent + 2;
+        format!(
+            "try {}\n{}catch {}",
+            self.expr(&try_catch.body, column + 4, indent + 4),
+            " ".repeat(catch_indent),
+            self.expr(&try_catch.handler, catch_indent + 6, catch_indent),
+        )
+    }
+
+    /// Each expression of the sequence goes on its own line. A statement cannot
+    /// continue on an unindented line, so those after the first are indented at
+    /// least a bit.
+    fn sequence(&self, sequence: &Sequence, column: usize, indent: usize) -> String {
+        let rest_indent = indent.max(2);
+        let mut out = String::new();
+        let (mut current, mut column, mut indent) = (sequence, column, indent);
+        loop {
+            if first_needs_parens(&current.first) {
+                out.push_str(&format!(
+                    "({})",
+                    self.expr(&current.first, column + 1, indent + 1)
+                ));
+            } else {
+                out.push_str(&self.expr(&current.first, column, indent));
+            }
+            out.push_str(";\n");
+            out.push_str(&" ".repeat(rest_indent));
+            (column, indent) = (rest_indent, rest_indent);
+            match &current.second {
+                Expr::Sequence(next) => current = next,
+                last => {
+                    out.push_str(&self.expr(last, column, indent));
+                    return out;
+                }
+            }
+        }
+    }
+
+    fn apply(&self, apply: &Apply, column: usize, indent: usize) -> String {
+        let (head, args) = spine(&apply.function, &apply.argument, as_apply);
+        let arg_indent = indent + 2;
+        let mut out = self.atom(head, false, column, indent);
+        for (i, arg) in args.iter().enumerate() {
+            out.push('\n');
+            out.push_str(&" ".repeat(arg_indent));
+            out.push_str(&self.atom(arg, i + 1 == args.len(), arg_indent, arg_indent));
+        }
+        out
+    }
+
+    fn atom(&self, expr: &Expr, trailing: bool, column: usize, indent: usize) -> String {
+        if needs_parens(expr, trailing) {
+            format!("({})", self.expr(expr, column + 1, indent + 1))
+        } else {
+            self.expr(expr, column, indent)
+        }
+    }
+}
+
+fn flat_expr(expr: &Expr) -> String {
+    match expr {
+        Expr::Lambda(lambda) => format!("\\{} -> {}", lambda.param, flat_expr(&lambda.body)),
+        Expr::Apply(apply) => {
+            let (head, args) = spine(&apply.function, &apply.argument, as_apply);
+            let mut out = flat_atom(head, false);
+            for (i, arg) in args.iter().enumerate() {
+                out.push(' ');
+                out.push_str(&flat_atom(arg, i + 1 == args.len()));
+            }
+            out
+        }
+        Expr::Lookup(lookup) => lookup.name.clone(),
+        Expr::LitInteger(lit) => format!("{:+}", lit.value),
+        Expr::LitString(lit) => quote(&lit.value),
+        Expr::TryCatch(try_catch) => format!(
+            "try {} catch {}",
+            flat_expr(&try_catch.body),
+            flat_expr(&try_catch.handler)
+        ),
+        Expr::Sequence(sequence) => {
+            let first = flat_expr(&sequence.first);
+            if first_needs_parens(&sequence.first) {
+                format!("({}); {}", first, flat_expr(&sequence.second))
+            } else {
+                format!("{}; {}", first, flat_expr(&sequence.second))
+            }
+        }
+    }
+}
+
+fn flat_atom(expr: &Expr, trailing: bool) -> String {
+    if needs_parens(expr, trailing) {
+        format!("({})", flat_expr(expr))
+    } else {
+        flat_expr(expr)
+    }
+}
+
+/// Whether `expr` needs parentheses to be used as the head or an argument of
+/// an application. Only the last argument may be a bare lambda, since its body
+/// extends to the end of the application anyway.
+fn needs_parens(expr: &Expr, trailing: bool) -> bool {
+    match expr {
+        Expr::Apply(_) | Expr::TryCatch(_) | Expr::Sequence(_) => true,
+        Expr::Lambda(_) => !trailing,
+        Expr::Lookup(_) | Expr::LitInteger(_) | Expr::LitString(_) => false,
+    }
+}
+
+/// Whether `expr` needs parentheses to come before `;`, which it would
+/// otherwise take in, along with the rest of the sequence.
+fn first_needs_parens(expr: &Expr) -> bool {
+    match expr {
+        Expr::Lambda(_) | Expr::TryCatch(_) | Expr::Sequence(_) => true,
+        Expr::Apply(_) | Expr::Lookup(_) | Expr::LitInteger(_) | Expr::LitString(_) => false,
+    }
+}
+
+/// Flattens nested applications `((f a) b) c`, given as `f a b` and `c`, into
+/// `f` and `[a, b, c]`. `as_apply` splits an application into its function
+/// and argument, so that this serves the locally nameless tree too.
+pub(crate) fn spine<'a, E>(
+    function: &'a E,
+    argument: &'a E,
+    as_apply: impl Fn(&'a E) -> Option<(&'a E, &'a E)>,
+) -> (&'a E, Vec<&'a E>) {
+    let mut args = vec![argument];
+    let mut head = function;
+    while let Some((function, argument)) = as_apply(head) {
+        args.push(argument);
+        head = function;
+    }
+    args.reverse();
+    (head, args)
+}
+
+fn as_apply(expr: &Expr) -> Option<(&Expr, &Expr)> {
+    match expr {
+        Expr::Apply(apply) => Some((&apply.function, &apply.argument)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::ParserPos;
+    use crate::pipeline::parser;
+
+    fn erase_spans(expr: &mut Expr) {
+        let no_span = (ParserPos::new(0, 0), ParserPos::new(0, 0));
+        match expr {
+            Expr::Lambda(lambda) => {
+                lambda.span = no_span;
+                erase_spans(&mut lambda.body);
+            }
+            Expr::Apply(apply) => {
+                apply.span = no_span;
+                erase_spans(&mut apply.function);
+                erase_spans(&mut apply.argument);
+            }
+            Expr::Lookup(lookup) => lookup.span = no_span,
+            Expr::LitInteger(lit) => lit.span = no_span,
+            Expr::LitString(lit) => lit.span = no_span,
+            Expr::TryCatch(try_catch) => {
+                try_catch.span = no_span;
+                erase_spans(&mut try_catch.body);
+                erase_spans(&mut try_catch.handler);
+            }
+            Expr::Sequence(sequence) => {
+                sequence.span = no_span;
+                erase_spans(&mut sequence.first);
+                erase_spans(&mut sequence.second);
+            }
+        }
+    }
+
+    fn parse_without_spans(text: &str) -> Vec<Stmt> {
+        let mut stmts = parser::parse_program(text).unwrap();
+        for stmt in stmts.iter_mut() {
+            match stmt {
+                Stmt::Expr(expr) => erase_spans(expr),
+                Stmt::Decl(decl) => {
+                    decl.span = (ParserPos::new(0, 0), ParserPos::new(0, 0));
+                    erase_spans(&mut decl.expr);
+                }
+                Stmt::Import(import) => import.span = (ParserPos::new(0, 0), ParserPos::new(0, 0)),
+                Stmt::Export(export) => export.span = (ParserPos::new(0, 0), ParserPos::new(0, 0)),
+                Stmt::Test(test) => {
+                    test.span = (ParserPos::new(0, 0), ParserPos::new(0, 0));
+                    erase_spans(&mut test.expr);
+                    erase_spans(&mut test.expected);
+                }
+            }
+        }
+        stmts
+    }
+
+    fn assert_round_trip(text: &str, width: usize) -> String {
+        let printed = print_program(&parser::parse_program(text).unwrap(), width);
+        assert_eq!(parse_without_spans(&printed), parse_without_spans(text));
+        printed
+    }
+
+    #[test]
+    fn uses_minimal_parentheses() {
+        assert_eq!(
+            assert_round_trip("((f  (g x)) (\\y -> y)) ((\\z -> z) +1) -2", 80),
+            "f (g x) (\\y -> y) ((\\z -> z) +1) -2\n"
+        );
+        assert_eq!(
+            assert_round_trip("let k = (\\x -> (\\y -> x))\nf +1 (\\k -> (k +4))", 80),
+            "let k = \\x -> \\y -> x\nf +1 \\k -> k +4\n"
+        );
+        assert_eq!(assert_round_trip("(f \\x -> x) y", 80), "f (\\x -> x) y\n");
+        assert_eq!(
+            assert_round_trip("f (try (g x) catch (\\e -> e)) \"y\\n\"", 80),
+            "f (try g x catch \\e -> e) \"y\\n\"\n"
+        );
+    }
+
+    #[test]
+    fn breaks_long_expressions() {
+        let source = "let compose = \\f -> \\g -> \\x -> f (g x) (plus (plus +1 +2) (plus +3 +4))";
+        let printed = assert_round_trip(source, 32);
+        assert_eq!(
+            printed,
+            "let compose = \\f -> \\g -> \\x ->
+  f
+    (g x)
+    (plus
+       (plus +1 +2)
+       (plus +3 +4))
+"
+        );
+        assert!(printed.lines().all(|line| line.len() <= 32));
+    }
+
+    #[test]
+    fn breaks_try_catch_before_catch() {
+        let source = "try fetch (plus +1 +2) (plus +3 +4) catch \\error -> report error";
+        let printed = assert_round_trip(source, 40);
+        assert_eq!(
+            printed,
+            "try fetch (plus +1 +2) (plus +3 +4)
+  catch \\error -> report error
+"
+        );
+    }
+
+    #[test]
+    fn sequences_with_semicolons() {
+        assert_eq!(
+            assert_round_trip("f ((\\x -> x); (a; b); try c catch h; d)", 80),
+            "f ((\\x -> x); (a; b); try c catch h; d)\n"
+        );
+        let source = "let count = \\c -> set c (plus (get c) +1); set c (plus (get c) +1); get c";
+        assert_eq!(
+            assert_round_trip(source, 40),
+            "let count = \\c ->
+  set c (plus (get c) +1);
+  set c (plus (get c) +1);
+  get c
+"
+        );
+        assert_eq!(
+            assert_round_trip("println \"first\"; println \"second\"", 20),
+            "println \"first\";
+  println \"second\"
+"
+        );
+    }
+
+    #[test]
+    fn prints_imports_and_exports() {
+        assert_eq!(
+            assert_round_trip("import  \"lib/math.lc\"\nimport math as  m\nexport a,b", 80),
+            "import \"lib/math.lc\"\nimport math as m\nexport a, b\n"
+        );
+    }
+
+    #[test]
+    fn prints_tests_on_one_line_or_breaks_them_before_the_expectation() {
+        assert_eq!(
+            assert_round_trip("test  \"adds\" = plus +1 +1==+2", 80),
+            "test \"adds\" = plus +1 +1 == +2\n"
+        );
+        assert_eq!(
+            assert_round_trip(
+                "test \"composes\" = compose (plus +1) (plus +2) +3 == +6",
+                40
+            ),
+            "test \"composes\" =
+  compose (plus +1) (plus +2) +3
+  == +6
+"
+        );
+    }
+
+    #[test]
+    fn keeps_blank_lines_between_statements() {
+        assert_eq!(
+            assert_round_trip("let a = +1\nlet b =\n  a\n\n\na\n", 80),
+            "let a = +1\nlet b = a\n\na\n"
+        );
+    }
+}
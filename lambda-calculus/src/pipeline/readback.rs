@@ -0,0 +1,377 @@
+use std::{cell::RefCell, collections::HashSet};
+
+use super::{
+    locally_nameless_tree::{BoundVar, Expr, FreeVar, Lookup},
+    modules,
+    parse_tree::quote,
+    printer::spine,
+    runtime::{FunctionOrigin, Locals, RTFunction, RTRef, RTValue},
+};
+
+/// Shows a function value as a lambda term, giving fresh names to its
+/// parameters and writing out the values it captured in place of the
+/// variables that refer to them, e.g. `\a -> plus +1 a`.
+pub fn function(function: &RTFunction) -> String {
+    let mut readback = Readback::default();
+    readback.reserve_function(function);
+    readback.function(function, 0).text
+}
+
+/// Shows a value the way its `Display` does, except that globals `is_stale`
+/// tells were redefined since, under [`modules::Binding::Snapshot`], are
+/// written with their version, e.g. `a@1` for the first `a`.
+pub fn value_with_versions(value: &RTValue, is_stale: &dyn Fn(&str) -> bool) -> String {
+    let mut readback = Readback {
+        is_stale: Some(is_stale),
+        ..Readback::default()
+    };
+    match value {
+        RTValue::Function(function) => {
+            readback.reserve_function(function);
+            readback.function(function, 0).text
+        }
+        RTValue::Ref(_) => {
+            readback.reserve_value(value);
+            readback.value(value, 0).text
+        }
+        RTValue::Integer(_) | RTValue::String(_) => value.to_string(),
+    }
+}
+
+/// Shows a ref as the application of `ref` to its current contents, e.g.
+/// `ref +1`. A ref met again while showing its own contents is shown as
+/// `<cycle>`.
+pub fn reference(reference: &RTRef) -> String {
+    let value = RTValue::Ref(reference.clone());
+    let mut readback = Readback::default();
+    readback.reserve_value(&value);
+    readback.value(&value, 0).text
+}
+
+/// How a piece of printed term binds, to decide where it needs parentheses.
+enum Shape {
+    Atom,
+    Lambda,
+    Application,
+    /// Like a lambda, extends as far as possible, but is never left bare in
+    /// an application: `try` and sequences.
+    Open,
+}
+
+struct Term {
+    text: String,
+    shape: Shape,
+}
+
+impl Term {
+    fn new(text: String, shape: Shape) -> Self {
+        Self { text, shape }
+    }
+
+    /// Used as head or argument of an application. Only the last argument may
+    /// be a bare lambda.
+    fn as_atom(&self, trailing: bool) -> String {
+        match self.shape {
+            Shape::Atom => self.text.clone(),
+            Shape::Lambda if trailing => self.text.clone(),
+            Shape::Lambda | Shape::Application | Shape::Open => format!("({})", self.text),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Readback<'a> {
+    /// Names of globals mentioned anywhere in the printed term, which must not
+    /// be used to name parameters.
+    reserved: HashSet<String>,
+    /// Refs whose contents are being gone through, to stop at cycles.
+    open_refs: RefCell<Vec<RTRef>>,
+    /// Tells which globals are previous versions, to write with their version.
+    is_stale: Option<&'a dyn Fn(&str) -> bool>,
+}
+
+impl Readback<'_> {
+    fn reserve_function(&mut self, function: &RTFunction) {
+        match &function.origin {
+            FunctionOrigin::Lambda { body, captured } => self.reserve_expr(body, 1, captured),
+            FunctionOrigin::Native { applied, .. } => {
+                for value in applied {
+                    self.reserve_value(value);
+                }
+            }
+            FunctionOrigin::CallCC | FunctionOrigin::Continuation(_) => {}
+        }
+    }
+
+    fn reserve_value(&mut self, value: &RTValue) {
+        match value {
+            RTValue::Function(function) => self.reserve_function(function),
+            RTValue::Ref(reference) => {
+                if let Some(contents) = self.enter_ref(reference) {
+                    self.reserve_value(&contents);
+                    self.leave_ref();
+                }
+            }
+            RTValue::Integer(_) | RTValue::String(_) => {}
+        }
+    }
+
+    /// The contents of `reference`, unless it is already being gone through.
+    fn enter_ref(&self, reference: &RTRef) -> Option<RTValue> {
+        let mut open_refs = self.open_refs.borrow_mut();
+        if open_refs.iter().any(|open| open.same_cell(reference)) {
+            return None;
+        }
+        open_refs.push(reference.clone());
+        Some(reference.get())
+    }
+
+    fn leave_ref(&self) {
+        self.open_refs.borrow_mut().pop();
+    }
+
+    fn reserve_expr(&mut self, expr: &Expr, depth: usize, captured: &Locals) {
+        match expr {
+            Expr::Lambda(lambda) => self.reserve_expr(&lambda.body, depth + 1, captured),
+            Expr::Apply(apply) => {
+                self.reserve_expr(&apply.function, depth, captured);
+                self.reserve_expr(&apply.argument, depth, captured);
+            }
+            Expr::TryCatch(try_catch) => {
+                self.reserve_expr(&try_catch.body, depth, captured);
+                self.reserve_expr(&try_catch.handler, depth, captured);
+            }
+            Expr::Sequence(sequence) => {
+                self.reserve_expr(&sequence.first, depth, captured);
+                self.reserve_expr(&sequence.second, depth, captured);
+            }
+            Expr::Lookup(Lookup::Free(FreeVar { name, .. })) => {
+                self.reserved
+                    .insert(modules::written_name(name).to_string());
+            }
+            Expr::Lookup(Lookup::Bound(BoundVar { de_brujn_index, .. })) => {
+                if *de_brujn_index >= depth {
+                    if let Some(value) = captured.lookup(de_brujn_index - depth) {
+                        self.reserve_value(&value);
+                    }
+                }
+            }
+            Expr::LitInteger(_) | Expr::LitString(_) => {}
+        }
+    }
+
+    /// How the global `key` is written: by its name, unless it is a previous
+    /// version, whose first one has no version in its key.
+    fn global_name(&self, key: &str) -> String {
+        let written = modules::written_name(key);
+        match self.is_stale {
+            Some(is_stale) if is_stale(key) && written == key => format!("{}@1", key),
+            Some(is_stale) if is_stale(key) => key.to_string(),
+            _ => written.to_string(),
+        }
+    }
+
+    fn fresh_name(&self, depth: usize) -> String {
+        (0..)
+            .map(|i: usize| {
+                let letter = char::from(b'a' + (i % 26) as u8);
+                match i / 26 {
+                    0 => letter.to_string(),
+                    n => format!("{}{}", letter, n),
+                }
+            })
+            .filter(|name| !self.reserved.contains(name))
+            .nth(depth)
+            .unwrap()
+    }
+
+    /// Prints a function whose parameters are named starting from the
+    /// `first_name`-th fresh name, so that it reads clearly when nested inside
+    /// another term.
+    fn function(&self, function: &RTFunction, first_name: usize) -> Term {
+        match &function.origin {
+            FunctionOrigin::Lambda { body, captured } => {
+                let mut names = vec![];
+                self.lambda(body, captured, first_name, &mut names)
+            }
+            FunctionOrigin::Native {
+                name,
+                arity,
+                applied,
+            } => {
+                let native = format!("<native {}/{}>", name, arity);
+                if applied.is_empty() {
+                    return Term::new(native, Shape::Atom);
+                }
+                let mut text = native;
+                for (i, value) in applied.iter().enumerate() {
+                    text.push(' ');
+                    text.push_str(
+                        &self
+                            .value(value, first_name)
+                            .as_atom(i + 1 == applied.len()),
+                    );
+                }
+                Term::new(text, Shape::Application)
+            }
+            FunctionOrigin::CallCC => Term::new("<callcc>".to_string(), Shape::Atom),
+            FunctionOrigin::Continuation(_) => Term::new("<continuation>".to_string(), Shape::Atom),
+        }
+    }
+
+    fn value(&self, value: &RTValue, first_name: usize) -> Term {
+        match value {
+            RTValue::Function(function) => self.function(function, first_name),
+            RTValue::Integer(i) => Term::new(format!("{:+}", i), Shape::Atom),
+            RTValue::String(s) => Term::new(quote(s), Shape::Atom),
+            RTValue::Ref(reference) => match self.enter_ref(reference) {
+                Some(contents) => {
+                    let contents = self.value(&contents, first_name);
+                    self.leave_ref();
+                    Term::new(
+                        format!("ref {}", contents.as_atom(true)),
+                        Shape::Application,
+                    )
+                }
+                None => Term::new("<cycle>".to_string(), Shape::Atom),
+            },
+        }
+    }
+
+    fn lambda(
+        &self,
+        body: &Expr,
+        captured: &Locals,
+        first_name: usize,
+        names: &mut Vec<String>,
+    ) -> Term {
+        let name = self.fresh_name(first_name + names.len());
+        names.push(name.clone());
+        let body = self.expr(body, captured, first_name, names);
+        names.pop();
+        Term::new(format!("\\{} -> {}", name, body.text), Shape::Lambda)
+    }
+
+    fn expr(
+        &self,
+        expr: &Expr,
+        captured: &Locals,
+        first_name: usize,
+        names: &mut Vec<String>,
+    ) -> Term {
+        match expr {
+            Expr::Lambda(lambda) => self.lambda(&lambda.body, captured, first_name, names),
+            Expr::Apply(apply) => {
+                let (head, args) = spine(&apply.function, &apply.argument, as_apply);
+                let mut text = self.expr(head, captured, first_name, names).as_atom(false);
+                for (i, arg) in args.iter().enumerate() {
+                    text.push(' ');
+                    text.push_str(
+                        &self
+                            .expr(arg, captured, first_name, names)
+                            .as_atom(i + 1 == args.len()),
+                    );
+                }
+                Term::new(text, Shape::Application)
+            }
+            Expr::Lookup(Lookup::Free(FreeVar { name, .. })) => {
+                Term::new(self.global_name(name), Shape::Atom)
+            }
+            Expr::Lookup(Lookup::Bound(BoundVar { de_brujn_index, .. })) => {
+                let depth = names.len();
+                if *de_brujn_index < depth {
+                    Term::new(names[depth - 1 - de_brujn_index].clone(), Shape::Atom)
+                } else {
+                    match captured.lookup(de_brujn_index - depth) {
+                        Some(value) => self.value(&value, first_name + depth),
+                        None => Term::new("<unbound>".to_string(), Shape::Atom),
+                    }
+                }
+            }
+            Expr::LitInteger(i) => Term::new(format!("{:+}", i), Shape::Atom),
+            Expr::LitString(s) => Term::new(quote(s), Shape::Atom),
+            Expr::TryCatch(try_catch) => {
+                let body = self.expr(&try_catch.body, captured, first_name, names);
+                let handler = self.expr(&try_catch.handler, captured, first_name, names);
+                Term::new(
+                    format!("try {} catch {}", body.text, handler.text),
+                    Shape::Open,
+                )
+            }
+            Expr::Sequence(sequence) => {
+                let first = self.expr(&sequence.first, captured, first_name, names);
+                let second = self.expr(&sequence.second, captured, first_name, names);
+                let first = match first.shape {
+                    Shape::Atom | Shape::Application => first.text,
+                    // would take the rest of the sequence in
+                    Shape::Lambda | Shape::Open => format!("({})", first.text),
+                };
+                Term::new(format!("{}; {}", first, second.text), Shape::Open)
+            }
+        }
+    }
+}
+
+fn as_apply(expr: &Expr) -> Option<(&Expr, &Expr)> {
+    match expr {
+        Expr::Apply(apply) => Some((&apply.function, &apply.argument)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pipeline::runtime::{Globals, RTFunction, RTValue};
+    use crate::test_support::eval_in;
+
+    #[test]
+    fn shows_lambdas_with_fresh_names() {
+        let globals = Globals::new();
+        assert_eq!(
+            eval_in(&globals, r"\f -> \x -> f (f x)").to_string(),
+            r"\a -> \b -> a (a b)"
+        );
+        assert_eq!(
+            eval_in(&globals, r"\x -> \y -> y (\z -> z x)").to_string(),
+            r"\a -> \b -> b \c -> c a"
+        );
+    }
+
+    #[test]
+    fn substitutes_captured_values() {
+        let globals = Globals::new();
+        assert_eq!(
+            eval_in(&globals, r"(\x -> \y -> y x) +3").to_string(),
+            r"\a -> a +3"
+        );
+        assert_eq!(
+            eval_in(&globals, r"(\g -> \y -> g y) (\x -> \z -> x)").to_string(),
+            r"\a -> (\b -> \c -> b) a"
+        );
+    }
+
+    #[test]
+    fn avoids_names_of_globals() {
+        let mut globals = Globals::new();
+        globals.define("a", RTValue::Integer(1));
+        assert_eq!(eval_in(&globals, r"\x -> a x").to_string(), r"\b -> a b");
+    }
+
+    #[test]
+    fn shows_natives_with_name_and_arity() {
+        let mut globals = Globals::new();
+        globals.define(
+            "plus",
+            RTValue::Function(RTFunction::native("plus", 2, vec![], |arg, _| {
+                Ok(RTValue::Function(RTFunction::native("plus", 2, vec![arg], |arg, _| {
+                    Ok(arg)
+                })))
+            })),
+        );
+        assert_eq!(eval_in(&globals, "plus").to_string(), "<native plus/2>");
+        assert_eq!(
+            eval_in(&globals, r"plus (\x -> x)").to_string(),
+            r"<native plus/2> \a -> a"
+        );
+    }
+}
@@ -1,16 +1,70 @@
-use std::{collections::HashMap, fmt::Display, rc::Rc};
+#[cfg(feature = "sync")]
+use std::sync::PoisonError;
+use std::{collections::HashMap, fmt::Display};
+
+use super::{
+    cps::Continuation,
+    errors::EvalError,
+    locally_nameless_tree::Expr,
+    native::{Console, Stdio},
+    parse_tree::quote,
+    readback,
+};
+
+/// The pointer runtime values are shared through: `Rc` by default, or `Arc`
+/// with the `sync` feature, so that values can be sent to other threads.
+#[cfg(not(feature = "sync"))]
+pub use std::rc::Rc as Shared;
+#[cfg(feature = "sync")]
+pub use std::sync::Arc as Shared;
+
+/// What closures stored in runtime values must be: anything by default, or
+/// `Send + Sync` with the `sync` feature.
+#[cfg(not(feature = "sync"))]
+pub trait ThreadSafe {}
+#[cfg(not(feature = "sync"))]
+impl<T: ?Sized> ThreadSafe for T {}
+
+#[cfg(feature = "sync")]
+pub trait ThreadSafe: Send + Sync {}
+#[cfg(feature = "sync")]
+impl<T: ?Sized + Send + Sync> ThreadSafe for T {}
+
+/// The interior mutability behind refs: `RefCell` by default, or `Mutex` with
+/// the `sync` feature.
+#[cfg(not(feature = "sync"))]
+type Cell<T> = std::cell::RefCell<T>;
+#[cfg(feature = "sync")]
+type Cell<T> = std::sync::Mutex<T>;
 
 pub struct Globals {
     globals: HashMap<String, RTValue>,
+    /// What the natives of [`super::native::define_io`] write to and read from.
+    console: Shared<dyn Console>,
+}
+
+impl Default for Globals {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Globals {
     pub fn new() -> Self {
         Self {
             globals: HashMap::new(),
+            console: Shared::new(Stdio),
         }
     }
 
+    pub fn console(&self) -> &dyn Console {
+        &*self.console
+    }
+
+    pub fn set_console(&mut self, console: impl Console + 'static) {
+        self.console = Shared::new(console);
+    }
+
     pub fn lookup(&self, name: &str) -> Option<RTValue> {
         self.globals.get(name).map(Clone::clone)
     }
@@ -18,11 +72,19 @@ impl Globals {
     pub fn define(&mut self, name: &str, val: RTValue) {
         self.globals.insert(name.to_string(), val);
     }
+
+    pub fn undefine(&mut self, name: &str) {
+        self.globals.remove(name);
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.globals.keys().map(|name| &name[..])
+    }
 }
 
 #[derive(Clone)]
 pub struct Locals {
-    locals: Option<Rc<RTFrame>>,
+    locals: Option<Shared<RTFrame>>,
 }
 
 impl Locals {
@@ -31,7 +93,7 @@ impl Locals {
     }
     pub fn bind_local(&self, arg: RTValue) -> Locals {
         Self {
-            locals: Some(Rc::new(RTFrame {
+            locals: Some(Shared::new(RTFrame {
                 var: arg,
                 parent: self.locals.clone(),
             })),
@@ -57,22 +119,147 @@ impl Locals {
 #[derive(Clone)]
 pub struct RTFrame {
     pub var: RTValue,
-    pub parent: Option<Rc<RTFrame>>,
+    pub parent: Option<Shared<RTFrame>>,
 }
 
 #[derive(Clone)]
 pub enum RTValue {
     Function(RTFunction),
     Integer(i64),
+    String(Shared<str>),
+    Ref(RTRef),
+}
+
+/// A mutable cell made by `ref`. Copies of the value share the same cell, so
+/// `set` through one is seen by `get` through any other.
+#[derive(Clone)]
+pub struct RTRef {
+    cell: Shared<Cell<RTValue>>,
+}
+
+impl RTRef {
+    pub fn new(value: RTValue) -> Self {
+        Self {
+            cell: Shared::new(Cell::new(value)),
+        }
+    }
+
+    pub fn get(&self) -> RTValue {
+        self.with_contents(|contents| contents.clone())
+    }
+
+    /// Replaces the contents, giving back what they were.
+    pub fn set(&self, value: RTValue) -> RTValue {
+        self.with_contents(|contents| std::mem::replace(contents, value))
+    }
+
+    /// Whether both are the same cell, rather than cells with equal contents.
+    pub fn same_cell(&self, other: &RTRef) -> bool {
+        Shared::ptr_eq(&self.cell, &other.cell)
+    }
+
+    #[cfg(not(feature = "sync"))]
+    fn with_contents<R>(&self, f: impl FnOnce(&mut RTValue) -> R) -> R {
+        f(&mut self.cell.borrow_mut())
+    }
+
+    #[cfg(feature = "sync")]
+    fn with_contents<R>(&self, f: impl FnOnce(&mut RTValue) -> R) -> R {
+        // the lock is only held to clone or replace the contents, which cannot
+        // leave them half-changed, so a poisoned lock is still good to use
+        let mut contents = self.cell.lock().unwrap_or_else(PoisonError::into_inner);
+        f(&mut contents)
+    }
+}
+
+impl std::fmt::Debug for RTValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
 }
 
 impl Display for RTValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            RTValue::Function(_) => f.write_str("<Function>"),
+            RTValue::Function(function) => f.write_str(&readback::function(function)),
             RTValue::Integer(i) => f.write_fmt(format_args!("{}", *i)),
+            RTValue::String(s) => f.write_str(&quote(s)),
+            RTValue::Ref(reference) => f.write_str(&readback::reference(reference)),
         }
     }
 }
 
-pub type RTFunction = Rc<dyn Fn(RTValue, &Globals) -> Result<RTValue, String>>;
+#[cfg(not(feature = "sync"))]
+pub type NativeFn = dyn Fn(RTValue, &Globals) -> Result<RTValue, EvalError>;
+#[cfg(feature = "sync")]
+pub type NativeFn = dyn Fn(RTValue, &Globals) -> Result<RTValue, EvalError> + Send + Sync;
+
+#[derive(Clone)]
+pub struct RTFunction {
+    call: Shared<NativeFn>,
+    pub origin: FunctionOrigin,
+}
+
+/// Where a function value came from, so that it can be shown to the user.
+#[derive(Clone)]
+pub enum FunctionOrigin {
+    /// A lambda from the source, closing over the locals it was created in.
+    Lambda {
+        body: Shared<Expr>,
+        captured: Locals,
+    },
+    /// A function implemented in Rust, maybe already applied to some of its
+    /// arguments.
+    Native {
+        name: Shared<str>,
+        arity: usize,
+        applied: Vec<RTValue>,
+    },
+    /// `callcc`, which only the CPS evaluator can apply.
+    CallCC,
+    /// A continuation captured by `callcc`, which resumes it when applied.
+    Continuation(Continuation),
+}
+
+impl RTFunction {
+    pub fn new(
+        origin: FunctionOrigin,
+        call: impl Fn(RTValue, &Globals) -> Result<RTValue, EvalError> + ThreadSafe + 'static,
+    ) -> Self {
+        Self {
+            call: Shared::new(call),
+            origin,
+        }
+    }
+
+    pub fn lambda(
+        body: Shared<Expr>,
+        captured: Locals,
+        call: impl Fn(RTValue, &Globals) -> Result<RTValue, EvalError> + ThreadSafe + 'static,
+    ) -> Self {
+        Self {
+            call: Shared::new(call),
+            origin: FunctionOrigin::Lambda { body, captured },
+        }
+    }
+
+    pub fn native(
+        name: &str,
+        arity: usize,
+        applied: Vec<RTValue>,
+        call: impl Fn(RTValue, &Globals) -> Result<RTValue, EvalError> + ThreadSafe + 'static,
+    ) -> Self {
+        Self {
+            call: Shared::new(call),
+            origin: FunctionOrigin::Native {
+                name: Shared::from(name),
+                arity,
+                applied,
+            },
+        }
+    }
+
+    pub fn call(&self, arg: RTValue, globals: &Globals) -> Result<RTValue, EvalError> {
+        (self.call)(arg, globals)
+    }
+}
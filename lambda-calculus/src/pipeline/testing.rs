@@ -0,0 +1,129 @@
+use super::{
+    church,
+    errors::TestFailure,
+    parse_tree::Test,
+    runtime::{Globals, RTRef, RTValue},
+};
+
+/// Checks that `actual`, what the expression of `test` evaluated to, is equal
+/// to `expected`, what the test expects.
+pub fn check(
+    test: &Test,
+    actual: &RTValue,
+    expected: &RTValue,
+    globals: &Globals,
+) -> Result<(), TestFailure> {
+    if equal(actual, expected, globals) {
+        return Ok(());
+    }
+    Err(TestFailure {
+        name: test.name.clone(),
+        actual: show(actual, globals),
+        expected: show(expected, globals),
+        span: test.span.clone(),
+    })
+}
+
+/// Whether two values are the same: numbers and strings when they are equal,
+/// refs when they hold equal values, and functions when they read back as the
+/// same term, or else encode the same Church numeral or boolean. Neither ever
+/// runs the functions.
+pub fn equal(a: &RTValue, b: &RTValue, globals: &Globals) -> bool {
+    equal_in(a, b, globals, &mut vec![])
+}
+
+/// Like [`equal`], taking the pairs of refs whose contents are being compared
+/// to be equal, so that refs that contain themselves are compared in the end.
+fn equal_in(
+    a: &RTValue,
+    b: &RTValue,
+    globals: &Globals,
+    open_refs: &mut Vec<(RTRef, RTRef)>,
+) -> bool {
+    match (a, b) {
+        (RTValue::Integer(a), RTValue::Integer(b)) => a == b,
+        (RTValue::String(a), RTValue::String(b)) => a == b,
+        (RTValue::Ref(a), RTValue::Ref(b)) => {
+            if a.same_cell(b)
+                || open_refs
+                    .iter()
+                    .any(|(x, y)| x.same_cell(a) && y.same_cell(b))
+            {
+                return true;
+            }
+            open_refs.push((a.clone(), b.clone()));
+            let equal = equal_in(&a.get(), &b.get(), globals, open_refs);
+            open_refs.pop();
+            equal
+        }
+        (RTValue::Function(_), RTValue::Function(_)) => {
+            if a.to_string() == b.to_string() {
+                return true;
+            }
+            match (church::decode(a, globals), church::decode(b, globals)) {
+                (Some(a), Some(b)) => a == b,
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+fn show(value: &RTValue, globals: &Globals) -> String {
+    match church::decode(value, globals) {
+        Some(decoded) => decoded.to_string(),
+        None => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::{native, prelude};
+    use crate::test_support::eval_in;
+
+    fn equal_str(a: &str, b: &str) -> bool {
+        let mut globals = Globals::new();
+        native::define_builtins(&mut globals);
+        equal(&eval_in(&globals, a), &eval_in(&globals, b), &globals)
+    }
+
+    #[test]
+    fn compares_functions_without_running_them() {
+        let mut globals = Globals::new();
+        native::define_builtins(&mut globals);
+        prelude::load(&mut globals).unwrap();
+        let y = eval_in(&globals, "y");
+        assert!(equal(&y, &y, &globals));
+        assert!(!equal(&y, &eval_in(&globals, "z"), &globals));
+
+        globals.define("count", eval_in(&globals, "ref +0"));
+        let counting = r"\f -> \x -> set count (plus (get count) +1); x";
+        assert!(!equal(
+            &eval_in(&globals, counting),
+            &eval_in(&globals, "zero"),
+            &globals
+        ));
+        assert_eq!(eval_in(&globals, "get count").to_string(), "0");
+    }
+
+    #[test]
+    fn compares_values_of_every_kind() {
+        assert!(equal_str("plus +1 +1", "+2"));
+        assert!(!equal_str("+2", "\"2\""));
+        assert!(equal_str("\"ab\"", "\"ab\""));
+        assert!(equal_str("ref +1", "ref (plus +0 +1)"));
+        assert!(!equal_str("ref +1", "ref +2"));
+        // refs that contain themselves
+        let cycle = r"(\r -> set r r; r) (ref +0)";
+        assert!(equal_str(cycle, cycle));
+        assert!(!equal_str(cycle, "ref (ref +0)"));
+        // the same numeral, written differently
+        assert!(equal_str(
+            r"(\n -> \f -> \x -> f (n f x)) (\f -> \x -> f x)",
+            r"\s -> \z -> s (s z)"
+        ));
+        assert!(equal_str(r"\x -> plus x", r"\y -> plus y"));
+        assert!(!equal_str(r"\x -> plus x", r"\y -> div y"));
+    }
+}
@@ -0,0 +1,68 @@
+//! Helpers shared by the unit tests of several modules.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::pipeline::{
+    errors::Source,
+    evaluator::{self, BuildOptions},
+    locally_nameless_tree::Expr,
+    parse_to_locally_nameless, parse_tree, parser,
+    runtime::{Globals, RTValue},
+};
+
+/// The expression of a statement, or of the definition it makes, ready to be
+/// built or run.
+pub fn prepare(text: &str) -> Expr {
+    let expr = match parser::parse_stmt(text).unwrap() {
+        parse_tree::Stmt::Expr(expr) => expr,
+        parse_tree::Stmt::Decl(decl) => decl.expr,
+        other => panic!("unexpected {:?}", other),
+    };
+    parse_to_locally_nameless::transform_expr(&expr, &Source::new("<test>", text))
+}
+
+/// Runs the expression of `text` with the default evaluator.
+pub fn eval_in(globals: &Globals, text: &str) -> RTValue {
+    evaluator::eval(&prepare(text), globals, BuildOptions::default()).unwrap()
+}
+
+/// A directory of its own under the system's temporary one, removed with
+/// everything in it when dropped, even if the test panicked.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Creates the directory for the test `name`, which must be unique among
+    /// tests, as they run in parallel.
+    pub fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("lc-{}-{}", name, process::id()));
+        fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes `contents` to the file `name` in the directory, creating the
+    /// directories on the way, and gives its path.
+    pub fn write(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.path.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,169 @@
+use std::{env, fs};
+
+use lambda_calculus::{Binding, Decoded, Interpreter, LoadError, RTFunction, RTValue};
+
+#[test]
+fn evaluates_expressions_and_keeps_definitions() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .eval_str(r"let triple = \x -> plus x (plus x x)")
+        .unwrap();
+    assert_eq!(interpreter.eval_as::<i64>("triple +5").unwrap(), 15);
+    assert!(interpreter.lookup("triple").is_some());
+
+    let error = interpreter.eval_as::<i64>(r"\x -> x").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        r"Type error: Expected number, got \a -> a"
+    );
+}
+
+#[test]
+fn defines_values_from_rust() {
+    let mut interpreter = Interpreter::without_prelude();
+    interpreter.define("limit", RTValue::Integer(10));
+    assert_eq!(interpreter.eval_as::<i64>("plus limit +1").unwrap(), 11);
+    assert!(interpreter.eval_str("two").is_err());
+}
+
+#[test]
+fn calls_back_into_registered_natives() {
+    let mut interpreter = Interpreter::new();
+    interpreter.register_native2("max", |a: i64, b: i64| a.max(b));
+    interpreter.register_native3(
+        "fold",
+        |n: i64, f: RTFunction, seed: RTValue, globals: &lambda_calculus::Globals| {
+            (0..n).try_fold(seed, |acc, _| f.call(acc, globals))
+        },
+    );
+    assert_eq!(interpreter.eval_as::<i64>("max +3 +7").unwrap(), 7);
+    assert_eq!(
+        interpreter
+            .eval_as::<i64>(r"fold +4 (\x -> plus x x) +1")
+            .unwrap(),
+        16
+    );
+    let error = interpreter.eval_str(r"max (\x -> x)").err().unwrap();
+    assert_eq!(
+        error.to_string(),
+        r"Type error: Expected number, got \a -> a"
+    );
+}
+
+#[test]
+fn decodes_church_encodings() {
+    let mut interpreter = Interpreter::new();
+    let value = interpreter.eval_str("leq two three").unwrap();
+    assert_eq!(
+        interpreter.decode_church(&value),
+        Some(Decoded::Boolean(true))
+    );
+    let value = interpreter.eval_str("plus +1 +1").unwrap();
+    assert_eq!(interpreter.decode_church(&value), None);
+}
+
+#[test]
+fn loads_files() {
+    let path = env::temp_dir().join(format!("interpreter-test-{}.lc", std::process::id()));
+    fs::write(&path, "let inc = \\x -> plus x step\nlet step = +1\n").unwrap();
+
+    let mut interpreter = Interpreter::new();
+    assert!(matches!(
+        interpreter.load_file(&path),
+        Err(LoadError::Run(_))
+    ));
+
+    let mut interpreter = Interpreter::new().allow_forward_references(true);
+    interpreter.load_file(&path).unwrap();
+    assert_eq!(interpreter.eval_as::<i64>("inc +1").unwrap(), 2);
+
+    fs::remove_file(&path).unwrap();
+    assert!(matches!(
+        interpreter.load_file(&path),
+        Err(LoadError::Read(_))
+    ));
+}
+
+#[test]
+fn evaluates_without_defining() {
+    let interpreter = Interpreter::new();
+    assert_eq!(interpreter.evaluate("plus +2 +2").unwrap().to_string(), "4");
+    let error = interpreter.evaluate("let x = +1").err().unwrap();
+    assert!(matches!(error, lambda_calculus::errors::Error::Parse(_)));
+}
+
+#[test]
+fn evaluates_with_the_options_it_was_built_with() {
+    let interpreter = Interpreter::new().allow_forward_references(true);
+    assert!(interpreter.evaluate(r"\x -> later x").is_ok());
+    assert!(Interpreter::new().evaluate(r"\x -> later x").is_err());
+    let interpreter = Interpreter::new().cps(true);
+    let value = interpreter
+        .evaluate(r"callcc \k -> plus +1 (k +2)")
+        .unwrap();
+    assert_eq!(value.to_string(), "2");
+}
+
+#[test]
+fn takes_back_options_that_are_turned_off() {
+    let interpreter = Interpreter::new().cps(true).cps(false);
+    let error = interpreter.evaluate(r"callcc \k -> k").unwrap_err();
+    assert!(error
+        .to_string()
+        .starts_with("Resolve error: Unbound global `callcc`"));
+    let interpreter = Interpreter::new().allow_io(true).allow_io(false);
+    assert!(interpreter.evaluate(r#"println "hi""#).is_err());
+
+    let mut interpreter = Interpreter::new().allow_io(true);
+    interpreter.eval_str(r"let print = \x -> x").unwrap();
+    let interpreter = interpreter.allow_io(false);
+    assert_eq!(interpreter.evaluate("print +1").unwrap().to_string(), "1");
+
+    let mut interpreter = Interpreter::new().binding(Binding::Snapshot);
+    interpreter.eval_str("let offset = +1").unwrap();
+    interpreter.eval_str("let offset = +2").unwrap();
+    let interpreter = interpreter.binding(Binding::Late);
+    assert_eq!(interpreter.evaluate("offset").unwrap().to_string(), "2");
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn shares_an_interpreter_between_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<RTValue>();
+    assert_send_sync::<Interpreter>();
+
+    let mut interpreter = Interpreter::new();
+    interpreter.eval_str(r"let square = \x -> mul x x").unwrap();
+    let interpreter = std::sync::Arc::new(interpreter);
+    let results: Vec<_> = (0..4)
+        .map(|n| {
+            let interpreter = interpreter.clone();
+            std::thread::spawn(move || {
+                let numeral = ["one", "two", "three", "(succ three)"][n];
+                let value = interpreter
+                    .evaluate(&format!("square {}", numeral))
+                    .unwrap();
+                interpreter.decode_church(&value)
+            })
+        })
+        .map(|handle| handle.join().unwrap())
+        .collect();
+    assert_eq!(results, [1, 4, 9, 16].map(|n| Some(Decoded::Numeral(n))));
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn evaluates_both_sides_of_par() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .eval_str(r"let sides = par (\n -> mul n n) (\n -> add n n) three")
+        .unwrap();
+    let first = interpreter.eval_str("fst sides").unwrap();
+    let second = interpreter.eval_str("snd sides").unwrap();
+    assert_eq!(interpreter.decode_church(&first), Some(Decoded::Numeral(9)));
+    assert_eq!(
+        interpreter.decode_church(&second),
+        Some(Decoded::Numeral(6))
+    );
+}
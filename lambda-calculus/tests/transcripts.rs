@@ -0,0 +1,24 @@
+use lambda_calculus::cli::{self, Config};
+
+/// Each `.transcript` file in `tests/transcripts` is a REPL session that must
+/// go on being answered the same way. To record a new one, write its inputs
+/// after `>>` prompts and run `lambda-calculus replay --bless` on it.
+#[test]
+fn replays_recorded_sessions() {
+    let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/transcripts");
+    let args = ["replay", directory].into_iter().map(String::from);
+    let config = Config::from_strings(args, |_| None).unwrap();
+    cli::run(&config).unwrap();
+}
+
+/// Those in `tests/io-transcripts` are replayed with `--allow-io`, and check
+/// what programs print.
+#[test]
+fn replays_sessions_that_print() {
+    let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/io-transcripts");
+    let args = ["replay", "--allow-io", directory]
+        .into_iter()
+        .map(String::from);
+    let config = Config::from_strings(args, |_| None).unwrap();
+    cli::run(&config).unwrap();
+}